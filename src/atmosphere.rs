@@ -0,0 +1,59 @@
+//! Barometric conversions between station pressure, sea-level pressure and
+//! altitude, using the measured temperature instead of the standard atmosphere
+
+/// Temperature lapse rate of the standard atmosphere in K/m
+const LAPSE_RATE: f64 = 0.0065;
+/// g·M / (R·L) for dry air
+const EXPONENT: f64 = 5.257;
+const ZERO_CELSIUS: f64 = 273.15;
+
+/// Pressure reduced to mean sea level (QFF) in Pa, from the station pressure
+/// in Pa, the temperature in °C and the station elevation in m
+pub fn sea_level_pressure(pressure: f64, temperature: f64, elevation: f64) -> f64 {
+    let rise = LAPSE_RATE * elevation;
+    pressure * (1.0 - rise / (temperature + rise + ZERO_CELSIUS)).powf(-EXPONENT)
+}
+
+/// Altitude in m at which the pressure in Pa is measured, given the
+/// temperature in °C and the pressure at sea level (QNH) in Pa
+pub fn altitude(pressure: f64, temperature: f64, sea_level_pressure: f64) -> f64 {
+    ((sea_level_pressure / pressure).powf(1.0 / EXPONENT) - 1.0) * (temperature + ZERO_CELSIUS)
+        / LAPSE_RATE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reduces_standard_atmosphere_to_sea_level() {
+        // ICAO standard atmosphere at 1000 m: 898.75 hPa and 8.5 °C
+        let pressure = sea_level_pressure(89_875.0, 8.5, 1000.0);
+
+        assert!((pressure - 101_325.0).abs() < 20.0, "pressure {pressure}");
+    }
+
+    #[test]
+    fn sea_level_is_unchanged_at_zero_elevation() {
+        assert_eq!(sea_level_pressure(100_653.27, 25.08, 0.0), 100_653.27);
+        assert_eq!(altitude(100_653.27, 25.08, 100_653.27), 0.0);
+    }
+
+    #[test]
+    fn altitude_inverts_sea_level_pressure() {
+        for elevation in [-50.0, 120.0, 540.0, 2350.0] {
+            let qnh = sea_level_pressure(95_000.0, 12.0, elevation);
+
+            let altitude = altitude(95_000.0, 12.0, qnh);
+            assert!((altitude - elevation).abs() < 1e-6, "altitude {altitude}");
+        }
+    }
+
+    #[test]
+    fn altitude_of_standard_atmosphere() {
+        // ICAO standard atmosphere at 2000 m: 795.01 hPa and 2 °C
+        let altitude = altitude(79_501.0, 2.0, 101_325.0);
+
+        assert!((altitude - 2000.0).abs() < 5.0, "altitude {altitude}");
+    }
+}
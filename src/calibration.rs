@@ -0,0 +1,166 @@
+//! Per-sensor corrections of the compensated readings
+
+use crate::psychrometrics;
+use serde::Deserialize;
+
+/// Corrections for temperature in °C, pressure in Pa and relative humidity
+/// in %, applied to the compensated readings
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Calibration {
+    pub temperature: Correction,
+    pub pressure: Correction,
+    pub humidity: Correction,
+}
+
+impl Calibration {
+    /// Corrected temperature, pressure and humidity
+    ///
+    /// The sensor measures humidity relative to its own temperature, so after
+    /// the humidity correction it is recomputed for the corrected temperature
+    /// at the same vapour pressure.
+    pub fn apply(&self, temperature: f64, pressure: f64, humidity: f64) -> (f64, f64, f64) {
+        let corrected_temperature = self.temperature.apply(temperature);
+        let vapour_pressure =
+            psychrometrics::vapour_pressure(temperature, self.humidity.apply(humidity));
+        let humidity = 100.0 * vapour_pressure
+            / psychrometrics::saturation_vapour_pressure(corrected_temperature);
+
+        (
+            corrected_temperature,
+            self.pressure.apply(pressure),
+            humidity.clamp(0.0, 100.0),
+        )
+    }
+}
+
+/// Linear correction `gain * raw + offset`, configured as a gain and offset or
+/// as two `[raw, reference]` points
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "CorrectionConfig")]
+pub struct Correction {
+    gain: f64,
+    offset: f64,
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl Correction {
+    pub fn apply(self, value: f64) -> f64 {
+        self.gain * value + self.offset
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CorrectionConfig {
+    gain: Option<f64>,
+    offset: Option<f64>,
+    points: Option<[[f64; 2]; 2]>,
+}
+
+impl TryFrom<CorrectionConfig> for Correction {
+    type Error = String;
+
+    fn try_from(config: CorrectionConfig) -> Result<Self, Self::Error> {
+        let correction = match config {
+            CorrectionConfig {
+                gain: None,
+                offset: None,
+                points: Some([[raw_low, low], [raw_high, high]]),
+            } => {
+                if raw_low == raw_high {
+                    return Err("calibration points need two different raw values".to_owned());
+                }
+                let gain = (high - low) / (raw_high - raw_low);
+                Self {
+                    gain,
+                    offset: low - gain * raw_low,
+                }
+            }
+            CorrectionConfig {
+                gain,
+                offset,
+                points: None,
+            } => Self {
+                gain: gain.unwrap_or(1.0),
+                offset: offset.unwrap_or(0.0),
+            },
+            _ => return Err("expected either points or a gain and offset".to_owned()),
+        };
+
+        if !(correction.gain.is_finite() && correction.offset.is_finite()) {
+            return Err("calibration gain and offset must be numbers".to_owned());
+        }
+
+        Ok(correction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn correction(toml: &str) -> Result<Correction, toml::de::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            correction: Correction,
+        }
+
+        toml::from_str::<Wrapper>(&format!("correction = {toml}")).map(|wrapper| wrapper.correction)
+    }
+
+    #[test]
+    fn applies_gain_and_offset() {
+        let offset = correction("{ offset = -1.5 }").expect("valid offset");
+        assert_eq!(offset.apply(25.0), 23.5);
+
+        let linear = correction("{ gain = 1.02, offset = -3 }").expect("valid gain and offset");
+        assert!((linear.apply(50.0) - 48.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fits_two_points() {
+        // reads 12 % in a 11.3 % salt and 76 % in a 75.3 % salt
+        let two_point =
+            correction("{ points = [[12, 11.3], [76, 75.3]] }").expect("valid two points");
+
+        assert!((two_point.apply(44.0) - 43.3).abs() < 1e-9);
+        let stretched = correction("{ points = [[10, 10], [60, 70]] }").expect("valid two points");
+        assert!((stretched.apply(35.0) - 40.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_ambiguous_corrections() {
+        assert!(correction("{ points = [[1, 1], [2, 2]], offset = 1 }").is_err());
+        assert!(correction("{ points = [[5, 1], [5, 2]] }").is_err());
+        assert!(correction("{ slope = 1 }").is_err());
+    }
+
+    #[test]
+    fn recomputes_humidity_for_corrected_temperature() {
+        // the sensor reads 2 °C high from self-heating, so the same vapour
+        // pressure is a higher relative humidity at the real temperature
+        let calibration = Calibration {
+            temperature: Correction {
+                gain: 1.0,
+                offset: -2.0,
+            },
+            ..Calibration::default()
+        };
+
+        let (temperature, pressure, humidity) = calibration.apply(25.0, 100_000.0, 50.0);
+        assert_eq!((temperature, pressure), (23.0, 100_000.0));
+        assert!((humidity - 56.3).abs() < 0.1, "humidity {humidity}");
+        let (_, _, unchanged) = Calibration::default().apply(25.0, 100_000.0, 50.0);
+        assert!((unchanged - 50.0).abs() < 1e-9);
+        assert_eq!(calibration.apply(25.0, 100_000.0, 95.0).2, 100.0);
+    }
+}
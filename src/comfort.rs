@@ -0,0 +1,143 @@
+//! Human comfort indices from temperature (°C) and relative humidity (%)
+
+use crate::psychrometrics;
+
+/// Heat index in °C after the NOAA Rothfusz regression, with the adjustments
+/// and the simple formula for mild conditions the National Weather Service uses
+pub fn heat_index(temperature: f64, humidity: f64) -> f64 {
+    let t = temperature * 9.0 / 5.0 + 32.0;
+    let rh = humidity;
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let fahrenheit = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut index = -42.379 + 2.049_015_23 * t + 10.143_331_27 * rh
+            - 0.224_755_41 * t * rh
+            - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            index -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            index += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+        }
+        index
+    };
+
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+/// Humidex as defined by Environment Canada
+pub fn humidex(temperature: f64, humidity: f64) -> f64 {
+    let dew_point = psychrometrics::dew_point(temperature, humidity) + 273.15;
+    let vapour_pressure = 6.11 * (5417.7530 * (1.0 / 273.16 - 1.0 / dew_point)).exp();
+
+    temperature + 0.5555 * (vapour_pressure - 10.0)
+}
+
+/// Apparent temperature in °C as used by the Australian Bureau of Meteorology,
+/// for still indoor air without wind
+pub fn apparent_temperature(temperature: f64, humidity: f64) -> f64 {
+    let vapour_pressure =
+        humidity / 100.0 * 6.105 * (17.27 * temperature / (237.7 + temperature)).exp();
+
+    temperature + 0.33 * vapour_pressure - 4.0
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Zone {
+    TooDry,
+    Comfortable,
+    TooHumid,
+}
+
+impl Zone {
+    pub const ALL: [Self; 3] = [Self::TooDry, Self::Comfortable, Self::TooHumid];
+
+    /// `None` when the humidity is unknown
+    pub fn classify(humidity: f64, min_humidity: f64, max_humidity: f64) -> Option<Self> {
+        if humidity.is_nan() {
+            None
+        } else if humidity < min_humidity {
+            Some(Self::TooDry)
+        } else if humidity > max_humidity {
+            Some(Self::TooHumid)
+        } else {
+            Some(Self::Comfortable)
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::TooDry => "too_dry",
+            Self::Comfortable => "comfortable",
+            Self::TooHumid => "too_humid",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fahrenheit(celsius: f64) -> f64 {
+        celsius * 9.0 / 5.0 + 32.0
+    }
+
+    fn celsius(fahrenheit: f64) -> f64 {
+        (fahrenheit - 32.0) * 5.0 / 9.0
+    }
+
+    #[test]
+    fn heat_index_matches_nws_table() {
+        // National Weather Service heat index chart, in °F
+        for (temperature, humidity, expected) in [
+            (80.0, 40.0, 80.0),
+            (90.0, 60.0, 100.0),
+            (96.0, 65.0, 121.0),
+            (104.0, 45.0, 124.0),
+            (86.0, 90.0, 105.0),
+        ] {
+            let index = fahrenheit(heat_index(celsius(temperature), humidity));
+            assert!(
+                (index - expected).abs() < 1.0,
+                "{temperature} °F at {humidity} %: {index} °F"
+            );
+        }
+    }
+
+    #[test]
+    fn heat_index_uses_simple_formula_when_mild() {
+        let index = fahrenheit(heat_index(celsius(70.0), 50.0));
+
+        assert!((index - 69.05).abs() < 0.01, "{index} °F");
+    }
+
+    #[test]
+    fn humidex_matches_environment_canada() {
+        // 30 °C with a dew point of 15 °C gives a humidex of 34
+        let humidity = 100.0 * psychrometrics::vapour_pressure(15.0, 100.0)
+            / psychrometrics::vapour_pressure(30.0, 100.0);
+
+        assert!((humidex(30.0, humidity) - 34.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn apparent_temperature_in_still_air() {
+        let apparent = apparent_temperature(30.0, 50.0);
+
+        assert!((apparent - 32.98).abs() < 0.01, "{apparent} °C");
+    }
+
+    #[test]
+    fn classifies_comfort_zone() {
+        assert_eq!(Zone::classify(25.0, 30.0, 60.0), Some(Zone::TooDry));
+        assert_eq!(Zone::classify(30.0, 30.0, 60.0), Some(Zone::Comfortable));
+        assert_eq!(Zone::classify(65.0, 30.0, 60.0), Some(Zone::TooHumid));
+        assert_eq!(Zone::classify(f64::NAN, 30.0, 60.0), None);
+    }
+}
@@ -0,0 +1,511 @@
+use crate::{
+    degree_days::{SeasonStart, MAX_GAP_SECONDS},
+    processing::ProcessingConfig,
+    sampling::SamplingOptions,
+    sensor::{Bus, SensorSpec},
+};
+use anyhow::Context;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: ListenConfig,
+    pub sensors: Vec<SensorSpec>,
+    pub sampling: SamplingOptions,
+    pub processing: ProcessingConfig,
+    pub collection: CollectionConfig,
+    pub station: StationConfig,
+    /// Classify the humidity into comfort zones, off unless configured
+    pub comfort: Option<ComfortConfig>,
+    pub greenhouse: GreenhouseConfig,
+    /// Accumulate heating and cooling degree days, off unless configured
+    pub degree_days: Option<DegreeDaysConfig>,
+    pub state: StateConfig,
+    /// Constant labels added to every exported series
+    pub labels: BTreeMap<String, String>,
+    pub prometheus: PrometheusConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    pub host: Ipv4Addr,
+    pub port: u16,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            host: Ipv4Addr::new(127, 0, 0, 1),
+            port: 3000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectionConfig {
+    /// Seconds between measurements of the background sampler
+    pub interval: f64,
+    /// Measure on every scrape instead of in the background
+    pub on_scrape: bool,
+    /// Seconds to wait for a sensor before giving up on a measurement
+    pub timeout: f64,
+}
+
+impl Default for CollectionConfig {
+    fn default() -> Self {
+        Self {
+            interval: 10.0,
+            on_scrape: false,
+            timeout: 1.0,
+        }
+    }
+}
+
+impl CollectionConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(self.interval)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.timeout)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StationConfig {
+    /// Elevation of the sensors in m, to reduce the pressure to sea level and
+    /// forecast from it
+    pub elevation: Option<f64>,
+    /// Current sea-level pressure in hPa, to estimate the altitude
+    pub qnh: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ComfortConfig {
+    /// Relative humidity in % below which the air is too dry
+    pub min_humidity: f64,
+    /// Relative humidity in % above which the air is too humid
+    pub max_humidity: f64,
+}
+
+impl Default for ComfortConfig {
+    fn default() -> Self {
+        Self {
+            min_humidity: 30.0,
+            max_humidity: 60.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GreenhouseConfig {
+    /// Difference between the leaf and the air temperature in °C, enables the
+    /// leaf vapour pressure deficit
+    pub leaf_temperature_offset: Option<f64>,
+    /// Accumulate growing degree days, off unless configured
+    pub growing_degree_days: Option<GrowingDegreeDaysConfig>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrowingDegreeDaysConfig {
+    /// Temperature in °C below which plants do not develop
+    pub base_temperature: f64,
+    /// Day of the year the accumulated degree days reset, as MM-DD in UTC
+    pub season_start: SeasonStart,
+}
+
+impl Default for GrowingDegreeDaysConfig {
+    fn default() -> Self {
+        Self {
+            base_temperature: 10.0,
+            season_start: SeasonStart::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DegreeDaysConfig {
+    /// Temperature in °C below which a building needs heating
+    pub heating_base_temperature: f64,
+    /// Temperature in °C above which a building needs cooling
+    pub cooling_base_temperature: f64,
+}
+
+impl Default for DegreeDaysConfig {
+    fn default() -> Self {
+        Self {
+            heating_base_temperature: 15.5,
+            cooling_base_temperature: 22.0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StateConfig {
+    /// JSON file to keep accumulated values in across restarts
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrometheusConfig {
+    pub path: String,
+    /// Also export the unprefixed temperature, pressure (hPa) and humidity (%) gauges
+    pub legacy_metric_names: bool,
+    /// Also export the uncompensated ADC values of each measurement
+    pub raw_adc: bool,
+    /// Serve the sensor registers as JSON on /debug/sensor
+    pub debug_endpoint: bool,
+}
+
+impl Default for PrometheusConfig {
+    fn default() -> Self {
+        Self {
+            path: "/metrics".to_owned(),
+            legacy_metric_names: false,
+            raw_adc: false,
+            debug_endpoint: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+
+        toml::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (index, sensor) in self.sensors.iter().enumerate() {
+            if self.sensors[..index]
+                .iter()
+                .any(|other| other.name == sensor.name)
+            {
+                anyhow::bail!("sensor name {:?} is used more than once", sensor.name);
+            }
+            if matches!(sensor.device, Bus::Spi(_)) && sensor.address.is_some() {
+                anyhow::bail!(
+                    "sensor {:?} is on an spi bus, where the address does not apply",
+                    sensor.name
+                );
+            }
+        }
+
+        self.sampling.settings().validate()?;
+        self.processing.validate()?;
+
+        collection_duration("interval", self.collection.interval)?;
+        collection_duration("timeout", self.collection.timeout)?;
+
+        if let Some(qnh) = self.station.qnh {
+            if !(qnh.is_finite() && qnh > 0.0) {
+                anyhow::bail!("qnh must be a positive pressure in hPa, got {qnh}");
+            }
+        }
+        if let Some(elevation) = self.station.elevation {
+            if !elevation.is_finite() {
+                anyhow::bail!("elevation must be a number of meters, got {elevation}");
+            }
+        }
+
+        if let Some(comfort) = &self.comfort {
+            if !(0.0 <= comfort.min_humidity
+                && comfort.min_humidity < comfort.max_humidity
+                && comfort.max_humidity <= 100.0)
+            {
+                anyhow::bail!(
+                    "comfort humidity range {}..{} must lie within 0..100",
+                    comfort.min_humidity,
+                    comfort.max_humidity
+                );
+            }
+        }
+
+        if let Some(offset) = self.greenhouse.leaf_temperature_offset {
+            if !offset.is_finite() {
+                anyhow::bail!("leaf temperature offset must be a number of degrees, got {offset}");
+            }
+        }
+        if let Some(growing_degree_days) = &self.greenhouse.growing_degree_days {
+            let base = growing_degree_days.base_temperature;
+            if !base.is_finite() {
+                anyhow::bail!("growing degree days base temperature must be a number, got {base}");
+            }
+        }
+
+        if let Some(degree_days) = &self.degree_days {
+            for (name, base) in [
+                ("heating", degree_days.heating_base_temperature),
+                ("cooling", degree_days.cooling_base_temperature),
+            ] {
+                if !base.is_finite() {
+                    anyhow::bail!("{name} base temperature must be a number, got {base}");
+                }
+            }
+        }
+        // every gap between background samples would be skipped as downtime
+        let integrates =
+            self.greenhouse.growing_degree_days.is_some() || self.degree_days.is_some();
+        if integrates && !self.collection.on_scrape && self.collection.interval > MAX_GAP_SECONDS {
+            anyhow::bail!(
+                "degree days need a collection interval of at most {MAX_GAP_SECONDS} seconds, got {}",
+                self.collection.interval
+            );
+        }
+
+        if !self.prometheus.path.starts_with('/') {
+            anyhow::bail!(
+                "prometheus path {:?} must start with a slash",
+                self.prometheus.path
+            );
+        }
+        if self.prometheus.debug_endpoint && self.prometheus.path == "/debug/sensor" {
+            anyhow::bail!("prometheus path /debug/sensor is taken by the debug endpoint");
+        }
+
+        Ok(())
+    }
+}
+
+/// Too large values would overflow the duration and too small ones round to
+/// zero, which the ticker does not accept
+fn collection_duration(name: &str, seconds: f64) -> anyhow::Result<Duration> {
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(duration) if !duration.is_zero() => Ok(duration),
+        _ => anyhow::bail!("collection {name} must be a positive number of seconds, got {seconds}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calibration::Correction,
+        sampling::{Filter, Oversampling},
+        sensor::Address,
+    };
+
+    #[test]
+    fn parses_full_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [listen]
+            host = "0.0.0.0"
+            port = 9100
+
+            [sampling]
+            profile = "weather"
+            temperature_oversampling = 2
+            humidity_oversampling = "skip"
+            filter = "off"
+
+            [processing]
+            median_window = 3
+
+            [collection]
+            interval = 2.5
+            timeout = 0.5
+
+            [station]
+            elevation = 42.5
+
+            [comfort]
+            max_humidity = 55
+
+            [greenhouse]
+            leaf_temperature_offset = -1.5
+
+            [greenhouse.growing_degree_days]
+            season_start = "04-01"
+
+            [degree_days]
+            heating_base_temperature = 18
+
+            [state]
+            file = "/var/lib/bme280-exporter/state.json"
+
+            [labels]
+            site = "greenhouse"
+
+            [prometheus]
+            path = "/bme280"
+
+            [[sensors]]
+            name = "indoor"
+            device = "/dev/i2c-1"
+            address = "0x76"
+            location = "living room"
+
+            [sensors.calibration]
+            temperature = { offset = -1.5 }
+            humidity = { points = [[12, 11.3], [76, 75.3]] }
+            "#,
+        )
+        .expect("failed to parse config");
+
+        assert_eq!(config.listen.host, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(config.listen.port, 9100);
+        let sampling = config.sampling.settings();
+        assert_eq!(sampling.temperature_oversampling, Oversampling::X2);
+        assert_eq!(sampling.pressure_oversampling, Oversampling::X1);
+        assert_eq!(sampling.humidity_oversampling, Oversampling::Skip);
+        assert_eq!(sampling.filter, Filter::Off);
+        assert_eq!(config.processing.median_window, 3);
+        assert_eq!(config.collection.interval(), Duration::from_millis(2500));
+        assert!(!config.collection.on_scrape);
+        assert_eq!(config.collection.timeout(), Duration::from_millis(500));
+        assert_eq!(config.station.elevation, Some(42.5));
+        assert_eq!(config.station.qnh, None);
+        let comfort = config.comfort.expect("comfort zones should be enabled");
+        assert_eq!((comfort.min_humidity, comfort.max_humidity), (30.0, 55.0));
+        assert_eq!(config.greenhouse.leaf_temperature_offset, Some(-1.5));
+        let growing_degree_days = config
+            .greenhouse
+            .growing_degree_days
+            .expect("growing degree days should be enabled");
+        assert_eq!(growing_degree_days.base_temperature, 10.0);
+        assert_eq!(growing_degree_days.season_start.to_string(), "04-01");
+        let degree_days = config.degree_days.expect("degree days should be enabled");
+        assert_eq!(degree_days.heating_base_temperature, 18.0);
+        assert_eq!(degree_days.cooling_base_temperature, 22.0);
+        assert_eq!(
+            config.state.file,
+            Some(PathBuf::from("/var/lib/bme280-exporter/state.json"))
+        );
+        assert_eq!(config.labels["site"], "greenhouse");
+        assert_eq!(config.prometheus.path, "/bme280");
+        let calibration = config.sensors[0]
+            .calibration
+            .expect("sensor should be calibrated");
+        assert_eq!(calibration.temperature.apply(25.0), 23.5);
+        assert!((calibration.humidity.apply(44.0) - 43.3).abs() < 1e-9);
+        assert_eq!(calibration.pressure, Correction::default());
+        assert_eq!(
+            config.sensors,
+            vec![SensorSpec {
+                name: "indoor".to_owned(),
+                device: Bus::I2c(PathBuf::from("/dev/i2c-1")),
+                address: Some(Address::Fixed(0x76)),
+                location: Some("living room".to_owned()),
+                calibration: Some(calibration),
+            }]
+        );
+        config.validate().expect("config should be valid");
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let err = toml::from_str::<Config>("[listen]\nhots = \"0.0.0.0\"\n")
+            .expect_err("unknown key should be rejected");
+
+        assert!(err.to_string().contains("unknown field `hots`"), "{err}");
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let err = toml::from_str::<Config>("[sampling]\nfilter = 3\n")
+            .expect_err("invalid filter should be rejected");
+
+        assert!(
+            err.to_string().contains("expected filter coefficient"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_sensor_names() {
+        let config: Config = toml::from_str(
+            r#"
+            [[sensors]]
+            name = "indoor"
+            device = "/dev/i2c-1"
+
+            [[sensors]]
+            name = "indoor"
+            device = "/dev/i2c-2"
+            "#,
+        )
+        .expect("failed to parse config");
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_address_on_spi_sensor() {
+        let config: Config = toml::from_str(
+            r#"
+            [[sensors]]
+            name = "hat"
+            device = "spi:/dev/spidev0.0"
+            address = "0x76"
+            "#,
+        )
+        .expect("failed to parse config");
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_non_positive_interval() {
+        let config: Config =
+            toml::from_str("[collection]\ninterval = 0\n").expect("failed to parse config");
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_interval_out_of_duration_range() {
+        for interval in ["1e300", "1e-10"] {
+            let config: Config = toml::from_str(&format!("[collection]\ninterval = {interval}\n"))
+                .expect("failed to parse config");
+
+            assert!(config.validate().is_err(), "interval {interval}");
+        }
+    }
+
+    #[test]
+    fn rejects_timeout_out_of_duration_range() {
+        for timeout in ["0", "1e300", "1e-10"] {
+            let config: Config = toml::from_str(&format!("[collection]\ntimeout = {timeout}\n"))
+                .expect("failed to parse config");
+
+            assert!(config.validate().is_err(), "timeout {timeout}");
+        }
+    }
+
+    #[test]
+    fn rejects_interval_too_long_for_degree_days() {
+        let config: Config = toml::from_str("[collection]\ninterval = 900\n[degree_days]\n")
+            .expect("failed to parse config");
+        assert!(config.validate().is_err());
+
+        let config: Config =
+            toml::from_str("[collection]\ninterval = 900\n").expect("failed to parse config");
+        config.validate().expect("interval without degree days");
+    }
+
+    #[test]
+    fn rejects_inverted_comfort_range() {
+        let config: Config =
+            toml::from_str("[comfort]\nmin_humidity = 70\n").expect("failed to parse config");
+
+        assert!(config.validate().is_err());
+    }
+}
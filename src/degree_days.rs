@@ -0,0 +1,240 @@
+//! Temperature integrated over time against a base temperature
+
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+pub const SECONDS_PER_DAY: f64 = 86_400.0;
+/// Longer gaps between samples, such as the exporter being stopped, are not
+/// integrated over
+pub const MAX_GAP_SECONDS: f64 = 600.0;
+const DAYS_IN_MONTH: [u32; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Accumulator {
+    pub degree_seconds: f64,
+    /// Unix time of the last sample
+    pub updated: Option<f64>,
+}
+
+impl Accumulator {
+    /// Integrate the time since the previous sample, weighted by how many
+    /// degrees the temperature is past the base, negative excess counts as 0
+    pub fn add(&mut self, at: f64, excess: f64) {
+        if excess.is_nan() {
+            return;
+        }
+
+        if let Some(updated) = self.updated {
+            let elapsed = at - updated;
+            if (0.0..=MAX_GAP_SECONDS).contains(&elapsed) {
+                self.degree_seconds += excess.max(0.0) * elapsed;
+            }
+        }
+        self.updated = Some(at);
+    }
+
+    pub fn degree_days(&self) -> f64 {
+        self.degree_seconds / SECONDS_PER_DAY
+    }
+}
+
+/// Growing degree days accumulated since the start of the current season
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct GrowingSeason {
+    /// First day of the season in days since the Unix epoch
+    pub started: i64,
+    pub accumulator: Accumulator,
+}
+
+impl GrowingSeason {
+    pub fn add(&mut self, at: f64, temperature: f64, base: f64, start: SeasonStart) {
+        let season = start.latest((at / SECONDS_PER_DAY).floor() as i64);
+        if season != self.started {
+            *self = Self {
+                started: season,
+                accumulator: Accumulator::default(),
+            };
+        }
+
+        self.accumulator.add(at, temperature - base);
+    }
+}
+
+/// Month and day a season starts on at midnight UTC, written as MM-DD
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct SeasonStart {
+    month: u32,
+    day: u32,
+}
+
+impl Default for SeasonStart {
+    fn default() -> Self {
+        Self { month: 1, day: 1 }
+    }
+}
+
+impl SeasonStart {
+    /// Latest season start on or before the day, both in days since the
+    /// Unix epoch
+    pub fn latest(self, day: i64) -> i64 {
+        let (year, _, _) = civil_from_days(day);
+        let start = days_from_civil(year, self.month, self.day);
+
+        if start <= day {
+            start
+        } else {
+            days_from_civil(year - 1, self.month, self.day)
+        }
+    }
+}
+
+impl FromStr for SeasonStart {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected a date as MM-DD, got {value:?}");
+
+        let (month, day) = value.split_once('-').ok_or_else(invalid)?;
+        let month: u32 = month.parse().map_err(|_| invalid())?;
+        let day: u32 = day.parse().map_err(|_| invalid())?;
+
+        let days_in_month = DAYS_IN_MONTH
+            .get(month.wrapping_sub(1) as usize)
+            .ok_or_else(invalid)?;
+        if !(1..=*days_in_month).contains(&day) {
+            return Err(invalid());
+        }
+
+        Ok(Self { month, day })
+    }
+}
+
+impl TryFrom<String> for SeasonStart {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for SeasonStart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}-{:02}", self.month, self.day)
+    }
+}
+
+/// Days since the Unix epoch of a proleptic Gregorian date, after Howard
+/// Hinnant's `days_from_civil`
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(2026, 10, 18), 20_744);
+        for days in [-1, 0, 11_016, 11_017, 20_744] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn finds_latest_season_start() {
+        let start: SeasonStart = "04-01".parse().expect("valid season start");
+
+        assert_eq!(
+            start.latest(days_from_civil(2026, 10, 18)),
+            days_from_civil(2026, 4, 1)
+        );
+        assert_eq!(
+            start.latest(days_from_civil(2026, 4, 1)),
+            days_from_civil(2026, 4, 1)
+        );
+        assert_eq!(
+            start.latest(days_from_civil(2026, 3, 31)),
+            days_from_civil(2025, 4, 1)
+        );
+    }
+
+    #[test]
+    fn parses_season_start() {
+        assert_eq!("12-31".parse(), Ok(SeasonStart { month: 12, day: 31 }));
+        assert!("02-29".parse::<SeasonStart>().is_err());
+        assert!("13-01".parse::<SeasonStart>().is_err());
+        assert!("00-01".parse::<SeasonStart>().is_err());
+        assert!("april".parse::<SeasonStart>().is_err());
+    }
+
+    #[test]
+    fn accumulates_excess_over_time() {
+        let mut accumulator = Accumulator::default();
+
+        accumulator.add(0.0, 5.0);
+        accumulator.add(300.0, 5.0);
+        accumulator.add(600.0, -3.0);
+        accumulator.add(900.0, f64::NAN);
+        accumulator.add(1200.0, 2.0);
+
+        // 5 K for 300 s, nothing while below the base, then 2 K for 600 s
+        assert_eq!(accumulator.degree_seconds, 1500.0 + 1200.0);
+    }
+
+    #[test]
+    fn skips_long_gaps() {
+        let mut accumulator = Accumulator::default();
+
+        accumulator.add(0.0, 5.0);
+        accumulator.add(3600.0, 5.0);
+
+        assert_eq!(accumulator.degree_seconds, 0.0);
+    }
+
+    #[test]
+    fn growing_season_resets_at_start() {
+        let start: SeasonStart = "04-01".parse().expect("valid season start");
+        let april = days_from_civil(2026, 4, 1) as f64 * SECONDS_PER_DAY;
+        let mut season = GrowingSeason::default();
+
+        season.add(april - 600.0, 20.0, 10.0, start);
+        season.add(april - 300.0, 20.0, 10.0, start);
+        assert_eq!(season.accumulator.degree_seconds, 3000.0);
+
+        season.add(april, 20.0, 10.0, start);
+        assert_eq!(season.started, days_from_civil(2026, 4, 1));
+        assert_eq!(season.accumulator.degree_seconds, 0.0);
+    }
+}
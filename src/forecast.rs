@@ -0,0 +1,234 @@
+//! Pressure tendency and the Zambretti forecaster
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+pub const WINDOWS: [(&str, Duration); 2] = [
+    ("1h", Duration::from_secs(3600)),
+    ("3h", Duration::from_secs(3 * 3600)),
+];
+/// Window the Zambretti forecaster takes the trend from
+const FORECAST_WINDOW: Duration = Duration::from_secs(3 * 3600);
+/// Changes below 1.6 hPa in 3 hours count as steady, as in synoptic reports
+const STEADY_RATE: f64 = 160.0 / (3.0 * 3600.0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trend {
+    Falling,
+    Steady,
+    Rising,
+}
+
+impl Trend {
+    /// Classify a pressure change rate in Pa/s
+    pub fn from_rate(rate: f64) -> Self {
+        if rate <= -STEADY_RATE {
+            Self::Falling
+        } else if rate >= STEADY_RATE {
+            Self::Rising
+        } else {
+            Self::Steady
+        }
+    }
+
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Self::Falling => -1.0,
+            Self::Steady => 0.0,
+            Self::Rising => 1.0,
+        }
+    }
+}
+
+/// Recent pressure readings, enough to cover the longest tendency window
+#[derive(Debug, Default)]
+pub struct PressureHistory {
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl PressureHistory {
+    pub fn record(&mut self, at: Instant, pressure: f64) {
+        if pressure.is_nan() {
+            return;
+        }
+
+        self.samples.push_back((at, pressure));
+
+        // keep one sample at or before the start of the longest window
+        let Some(start) = at.checked_sub(FORECAST_WINDOW) else {
+            return;
+        };
+        while self.samples.get(1).is_some_and(|&(at, _)| at <= start) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Pressure change rate in Pa/s over the window, `None` while the history
+    /// does not cover it yet
+    pub fn tendency(&self, window: Duration) -> Option<f64> {
+        let &(now, pressure) = self.samples.back()?;
+        let start = now.checked_sub(window)?;
+
+        let &(then, earlier) = self.samples.iter().rev().find(|&&(at, _)| at <= start)?;
+        Some((pressure - earlier) / (now - then).as_secs_f64())
+    }
+
+    pub fn trend(&self) -> Option<Trend> {
+        self.tendency(FORECAST_WINDOW).map(Trend::from_rate)
+    }
+}
+
+/// One of the 26 forecasts of the Negretti & Zambra pocket forecaster
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Forecast {
+    pub letter: char,
+    pub text: &'static str,
+}
+
+const FORECASTS: [&str; 26] = [
+    "Settled fine",
+    "Fine weather",
+    "Becoming fine",
+    "Fine, becoming less settled",
+    "Fine, possible showers",
+    "Fairly fine, improving",
+    "Fairly fine, possible showers early",
+    "Fairly fine, showery later",
+    "Showery early, improving",
+    "Changeable, mending",
+    "Fairly fine, showers likely",
+    "Rather unsettled, clearing later",
+    "Unsettled, probably improving",
+    "Showery, bright intervals",
+    "Showery, becoming less settled",
+    "Changeable, some rain",
+    "Unsettled, short fine intervals",
+    "Unsettled, rain later",
+    "Unsettled, some rain",
+    "Mostly very unsettled",
+    "Occasional rain, worsening",
+    "Rain at times, very unsettled",
+    "Rain at frequent intervals",
+    "Rain, very unsettled",
+    "Stormy, may improve",
+    "Stormy, much rain",
+];
+
+/// Zambretti numbers 1 to 9 are for falling, 10 to 19 for steady and 20 to
+/// 32 for rising pressure
+const LETTERS: &[u8; 32] = b"ABDHORUXZABEKNPSWXZABCFGIJLMQTYZ";
+
+/// Forecast from the sea-level pressure in Pa and its 3 hour trend, `None`
+/// when the pressure is not a number
+pub fn zambretti(sea_level_pressure: f64, trend: Trend) -> Option<Forecast> {
+    if !sea_level_pressure.is_finite() {
+        return None;
+    }
+
+    let hpa = sea_level_pressure / 100.0;
+    let (number, range) = match trend {
+        Trend::Falling => (127.0 - 0.12 * hpa, 1.0..=9.0),
+        Trend::Steady => (144.0 - 0.13 * hpa, 10.0..=19.0),
+        Trend::Rising => (185.0 - 0.16 * hpa, 20.0..=32.0),
+    };
+    let number = number.round().clamp(*range.start(), *range.end()) as usize;
+
+    let letter = LETTERS[number - 1];
+    Some(Forecast {
+        letter: char::from(letter),
+        text: FORECASTS[usize::from(letter - b'A')],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn history(start: Instant, pressures: impl IntoIterator<Item = f64>) -> PressureHistory {
+        let mut history = PressureHistory::default();
+        for (minute, pressure) in (0..).zip(pressures) {
+            history.record(start + MINUTE * minute, pressure);
+        }
+        history
+    }
+
+    #[test]
+    fn tendency_needs_a_full_window() {
+        let history = history(Instant::now(), [101_000.0; 59]);
+
+        assert_eq!(history.tendency(WINDOWS[0].1), None);
+        assert_eq!(history.trend(), None);
+    }
+
+    #[test]
+    fn tendency_over_windows() {
+        // falling 1 Pa per minute for four hours
+        let history = history(
+            Instant::now(),
+            (0..=240).map(|minute| 101_000.0 - minute as f64),
+        );
+
+        let rate = history.tendency(WINDOWS[0].1).expect("1h tendency");
+        assert!((rate + 1.0 / 60.0).abs() < 1e-9, "rate {rate}");
+        let rate = history.tendency(WINDOWS[1].1).expect("3h tendency");
+        assert!((rate + 1.0 / 60.0).abs() < 1e-9, "rate {rate}");
+        // 180 Pa in 3 hours is more than the 160 Pa of a steady trend
+        assert_eq!(history.trend(), Some(Trend::Falling));
+    }
+
+    #[test]
+    fn history_is_pruned_to_the_longest_window() {
+        let history = history(Instant::now(), [101_000.0; 600]);
+
+        assert_eq!(history.samples.len(), 181);
+    }
+
+    #[test]
+    fn missing_pressure_is_not_recorded() {
+        let history = history(Instant::now(), [101_000.0, f64::NAN]);
+
+        assert_eq!(history.samples.len(), 1);
+    }
+
+    #[test]
+    fn classifies_trend() {
+        assert_eq!(Trend::from_rate(0.0), Trend::Steady);
+        assert_eq!(Trend::from_rate(100.0 / 10_800.0), Trend::Steady);
+        assert_eq!(Trend::from_rate(200.0 / 10_800.0), Trend::Rising);
+        assert_eq!(Trend::from_rate(-200.0 / 10_800.0), Trend::Falling);
+    }
+
+    #[test]
+    fn zambretti_forecasts() {
+        let forecast = |pressure, trend| zambretti(pressure, trend).expect("forecast missing");
+
+        assert_eq!(
+            forecast(103_000.0, Trend::Rising),
+            Forecast {
+                letter: 'A',
+                text: "Settled fine"
+            }
+        );
+        // 1013 hPa: Z = 144 - 131.7 = 12
+        assert_eq!(forecast(101_300.0, Trend::Steady).letter, 'E');
+        // 1000 hPa: Z = 127 - 120 = 7
+        assert_eq!(forecast(100_000.0, Trend::Falling).letter, 'U');
+        assert_eq!(forecast(96_000.0, Trend::Falling).letter, 'Z');
+        // 960 hPa: Z = 185 - 153.6 = 31
+        assert_eq!(
+            forecast(96_000.0, Trend::Rising).text,
+            "Stormy, may improve"
+        );
+        assert_eq!(forecast(94_000.0, Trend::Rising).text, "Stormy, much rain");
+    }
+
+    #[test]
+    fn no_forecast_without_pressure() {
+        assert_eq!(zambretti(f64::NAN, Trend::Rising), None);
+        assert_eq!(zambretti(f64::INFINITY, Trend::Steady), None);
+    }
+}
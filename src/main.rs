@@ -1,37 +1,342 @@
-use axum::{
-    extract::State,
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    routing::get,
-    Router,
-};
-use bme280_rs::{Bme280, Configuration, Oversampling};
+use anyhow::Context;
+use axum::{extract::State, routing::get, Json, Router};
 use clap::Parser;
+use config::{ComfortConfig, Config, DegreeDaysConfig, GrowingDegreeDaysConfig};
+use degree_days::SeasonStart;
 use linux_embedded_hal::{Delay, I2cdev};
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use monitored::MonitoredSensor;
+use sampling::{SamplingOptions, SamplingSettings};
+use sensor::{Address, Bus, Sensor, SensorSpec, SimulatedSensor};
+use state::State as PersistentState;
 use std::{
+    collections::BTreeMap,
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant},
 };
-use tokio::sync::Mutex;
-use tracing::{info, Level};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+use tracing::{info, warn, Level};
+
+mod atmosphere;
+mod calibration;
+mod comfort;
+mod config;
+mod degree_days;
+mod forecast;
+mod monitored;
+mod processing;
+mod psychrometrics;
+mod sampling;
+mod sensor;
+mod state;
+mod worker;
 
 #[derive(Parser)]
 #[clap(name = "bme280-exporter", version, author)]
 struct Cli {
-    i2c_device_path: PathBuf,
+    #[arg(conflicts_with = "sensors")]
+    i2c_device_path: Option<PathBuf>,
+
+    /// TOML configuration file, command line options take precedence over it
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on [default: 127.0.0.1]
+    #[arg(long)]
+    host: Option<Ipv4Addr>,
+
+    /// Port to listen on [default: 3000]
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// Bus of the sensor: an I2C device path, i2c:<path> or spi:<path> for a spidev device
+    #[arg(long, value_name = "BUS", conflicts_with_all = ["i2c_device_path", "sensors"])]
+    bus: Option<Bus>,
+
+    /// I2C address of sensors without one: 0x76, 0x77 or auto to probe both
+    /// [default: 0x77]
+    #[arg(long)]
+    address: Option<Address>,
+
+    /// Sensor to export as name=<name>,device=<bus>[,address=<address>][,location=<location>],
+    /// can be repeated
+    #[arg(long = "sensor", value_name = "SENSOR")]
+    sensors: Vec<SensorSpec>,
+
+    /// Serve readings from a simulated sensor instead of real hardware
+    #[arg(long)]
+    simulate: bool,
+
+    /// Baseline temperature of the simulated sensor in °C
+    #[arg(long, default_value_t = 21.0, requires = "simulate")]
+    simulated_temperature: f32,
+
+    /// Baseline pressure of the simulated sensor in Pa
+    #[arg(long, default_value_t = 101_325.0, requires = "simulate")]
+    simulated_pressure: f32,
+
+    /// Baseline relative humidity of the simulated sensor in %
+    #[arg(long, default_value_t = 45.0, requires = "simulate")]
+    simulated_humidity: f32,
+
+    /// Seconds between measurements in the background [default: 10]
+    #[arg(long, value_name = "SECONDS", conflicts_with = "sample_on_scrape")]
+    interval: Option<f64>,
+
+    /// Measure on every scrape instead of in the background
+    #[arg(long)]
+    sample_on_scrape: bool,
+
+    /// Seconds to wait for a sensor before giving up on a measurement [default: 1]
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<f64>,
+
+    /// Elevation of the sensors in meters, enables the sea-level pressure gauge and the forecast
+    #[arg(long, value_name = "METERS", allow_negative_numbers = true)]
+    elevation: Option<f64>,
+
+    /// Current sea-level pressure in hPa, enables the altitude gauge
+    #[arg(long, value_name = "HPA")]
+    qnh: Option<f64>,
+
+    /// Relative humidity in % below which the air counts as too dry, enables the
+    /// comfort zone gauge [default: 30]
+    #[arg(long, value_name = "PERCENT")]
+    comfort_min_humidity: Option<f64>,
+
+    /// Relative humidity in % above which the air counts as too humid, enables
+    /// the comfort zone gauge [default: 60]
+    #[arg(long, value_name = "PERCENT")]
+    comfort_max_humidity: Option<f64>,
+
+    /// Difference between the leaf and the air temperature in °C, enables the
+    /// leaf vapour pressure deficit gauge
+    #[arg(long, value_name = "CELSIUS", allow_negative_numbers = true)]
+    leaf_temperature_offset: Option<f64>,
+
+    /// Temperature in °C below which plants do not develop, enables the growing
+    /// degree days gauge [default: 10]
+    #[arg(long, value_name = "CELSIUS", allow_negative_numbers = true)]
+    growing_degree_days_base: Option<f64>,
+
+    /// Day the growing degree days reset as MM-DD in UTC, enables the growing
+    /// degree days gauge [default: 01-01]
+    #[arg(long, value_name = "MM-DD")]
+    growing_season_start: Option<SeasonStart>,
+
+    /// Temperature in °C below which heating degree days accumulate, enables the
+    /// heating and cooling degree days [default: 15.5]
+    #[arg(long, value_name = "CELSIUS", allow_negative_numbers = true)]
+    heating_base_temperature: Option<f64>,
+
+    /// Temperature in °C above which cooling degree days accumulate, enables the
+    /// heating and cooling degree days [default: 22]
+    #[arg(long, value_name = "CELSIUS", allow_negative_numbers = true)]
+    cooling_base_temperature: Option<f64>,
+
+    /// JSON file to keep accumulated values in across restarts
+    #[arg(long, value_name = "FILE")]
+    state_file: Option<PathBuf>,
+
+    /// Also export the old temperature, pressure and humidity gauges
+    #[arg(long)]
+    legacy_metric_names: bool,
+
+    /// Also export the uncompensated ADC values of each measurement
+    #[arg(long)]
+    export_raw_adc: bool,
 
-    #[arg(long, default_value_t = Ipv4Addr::new(127, 0, 0, 1))]
-    host: Ipv4Addr,
+    /// Serve the sensor registers as JSON on /debug/sensor
+    #[arg(long)]
+    debug_endpoint: bool,
 
-    #[arg(long, default_value_t = 3000)]
-    port: u16,
+    #[command(flatten)]
+    sampling: SamplingOptions,
+}
+
+impl Cli {
+    fn load_config(&self) -> anyhow::Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        if let Some(host) = self.host {
+            config.listen.host = host;
+        }
+        if let Some(port) = self.port {
+            config.listen.port = port;
+        }
+
+        if !self.sensors.is_empty() {
+            config.sensors = self.sensors.clone();
+        }
+        if let Some(i2c_device_path) = &self.i2c_device_path {
+            config.sensors = vec![SensorSpec::new("bme280", Bus::I2c(i2c_device_path.clone()))];
+        }
+        if let Some(bus) = &self.bus {
+            config.sensors = vec![SensorSpec::new("bme280", bus.clone())];
+        }
+        if config.sensors.is_empty() {
+            if !self.simulate {
+                anyhow::bail!(
+                    "no sensors configured, pass a device path, --bus, --sensor or --config"
+                );
+            }
+            config.sensors = vec![SensorSpec::new("bme280", Bus::I2c(PathBuf::new()))];
+        }
+        if let Some(address) = self.address {
+            let mut i2c_sensors = config
+                .sensors
+                .iter_mut()
+                .filter(|sensor| matches!(sensor.device, Bus::I2c(_)))
+                .peekable();
+            if i2c_sensors.peek().is_none() {
+                anyhow::bail!("--address only applies to sensors on an i2c bus");
+            }
+            for sensor in i2c_sensors {
+                sensor.address.get_or_insert(address);
+            }
+        }
+
+        config.sampling = config.sampling.overridden_by(&self.sampling);
+
+        if let Some(interval) = self.interval {
+            config.collection.interval = interval;
+        }
+        if self.sample_on_scrape {
+            config.collection.on_scrape = true;
+        }
+        if let Some(timeout) = self.timeout {
+            config.collection.timeout = timeout;
+        }
+        if self.legacy_metric_names {
+            config.prometheus.legacy_metric_names = true;
+        }
+        if self.export_raw_adc {
+            config.prometheus.raw_adc = true;
+        }
+        if self.debug_endpoint {
+            config.prometheus.debug_endpoint = true;
+        }
+        if let Some(elevation) = self.elevation {
+            config.station.elevation = Some(elevation);
+        }
+        if let Some(qnh) = self.qnh {
+            config.station.qnh = Some(qnh);
+        }
+        if self.comfort_min_humidity.is_some() || self.comfort_max_humidity.is_some() {
+            let comfort = config.comfort.get_or_insert_with(ComfortConfig::default);
+            if let Some(min_humidity) = self.comfort_min_humidity {
+                comfort.min_humidity = min_humidity;
+            }
+            if let Some(max_humidity) = self.comfort_max_humidity {
+                comfort.max_humidity = max_humidity;
+            }
+        }
+
+        if let Some(offset) = self.leaf_temperature_offset {
+            config.greenhouse.leaf_temperature_offset = Some(offset);
+        }
+        if self.growing_degree_days_base.is_some() || self.growing_season_start.is_some() {
+            let growing_degree_days = config
+                .greenhouse
+                .growing_degree_days
+                .get_or_insert_with(GrowingDegreeDaysConfig::default);
+            if let Some(base_temperature) = self.growing_degree_days_base {
+                growing_degree_days.base_temperature = base_temperature;
+            }
+            if let Some(season_start) = self.growing_season_start {
+                growing_degree_days.season_start = season_start;
+            }
+        }
+        if self.heating_base_temperature.is_some() || self.cooling_base_temperature.is_some() {
+            let degree_days = config
+                .degree_days
+                .get_or_insert_with(DegreeDaysConfig::default);
+            if let Some(base_temperature) = self.heating_base_temperature {
+                degree_days.heating_base_temperature = base_temperature;
+            }
+            if let Some(base_temperature) = self.cooling_base_temperature {
+                degree_days.cooling_base_temperature = base_temperature;
+            }
+        }
+        if let Some(state_file) = &self.state_file {
+            config.state.file = Some(state_file.clone());
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
 }
 
 struct AppState {
     prometheus: PrometheusHandle,
-    bme280: Mutex<Bme280<I2cdev, Delay>>,
+    sensors: Vec<MonitoredSensor>,
+    sample_on_scrape: bool,
+    state_file: Option<StateFile>,
+}
+
+impl AppState {
+    async fn sample(&self) {
+        for sensor in &self.sensors {
+            sensor.measure().await;
+        }
+
+        if let Some(state_file) = &self.state_file {
+            state_file.save_if_due(&self.sensors);
+        }
+    }
+}
+
+/// Saving at most once a minute spares SD cards, a crash loses at most a
+/// minute of accumulated values
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct StateFile {
+    path: PathBuf,
+    saved: std::sync::Mutex<Option<Instant>>,
+    /// Save running on the blocking pool, the next one waits for it to finish
+    saving: std::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl StateFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            saved: Default::default(),
+            saving: Default::default(),
+        }
+    }
+
+    /// Saves in the background, the fsyncs can take seconds on an SD card
+    fn save_if_due(&self, sensors: &[MonitoredSensor]) {
+        let mut saved = self.saved.lock().expect("state file poisoned");
+        if saved.is_some_and(|saved| saved.elapsed() < STATE_SAVE_INTERVAL) {
+            return;
+        }
+        let mut saving = self.saving.lock().expect("state file poisoned");
+        if saving.as_ref().is_some_and(|saving| !saving.is_finished()) {
+            return;
+        }
+        *saved = Some(Instant::now());
+
+        let state = PersistentState {
+            sensors: sensors
+                .iter()
+                .map(|sensor| (sensor.name.clone(), sensor.state()))
+                .collect(),
+        };
+        let path = self.path.clone();
+        *saving = Some(tokio::task::spawn_blocking(move || {
+            if let Err(error) = state.save(&path) {
+                warn!(error = format!("{error:#}"), "failed to save state");
+            }
+        }));
+    }
 }
 
 #[tokio::main]
@@ -41,93 +346,1006 @@ async fn main() {
         .init();
 
     let cli = Cli::parse();
+    let config = cli.load_config().expect("invalid configuration");
+
+    let sampling = config.sampling.settings();
+    info!(
+        temperature_oversampling = %sampling.temperature_oversampling,
+        pressure_oversampling = %sampling.pressure_oversampling,
+        humidity_oversampling = %sampling.humidity_oversampling,
+        filter = %sampling.filter,
+        mode = %sampling.mode,
+        standby_time = sampling.standby_time.map(tracing::field::display),
+        measurement_time = ?sampling.measurement_time(),
+        "effective sampling configuration",
+    );
 
-    let prometheus = PrometheusBuilder::new()
+    let prometheus = config
+        .labels
+        .iter()
+        .fold(PrometheusBuilder::new(), |builder, (key, value)| {
+            builder.add_global_label(key, value)
+        })
         .install_recorder()
         .expect("failed to setup prometheus metrics");
 
-    metrics::register_gauge!("temperature");
-    metrics::describe_gauge!("temperature", "Temperature in °C");
-    metrics::register_gauge!("pressure");
-    metrics::describe_gauge!("pressure", "Air pressure in mPa");
-    metrics::register_gauge!("humidity");
-    metrics::describe_gauge!("humidity", "Relative humidity in %");
+    describe_metrics(&config);
+
+    let mut state = match &config.state.file {
+        Some(path) => PersistentState::load(path).expect("failed to load state file"),
+        None => PersistentState::default(),
+    };
+
+    let mut sensors: Vec<MonitoredSensor> = Vec::with_capacity(config.sensors.len());
+    for spec in &config.sensors {
+        let sensor = if cli.simulate {
+            info!(sensor = spec.name, "using simulated bme280 sensor");
+            let (temperature, pressure, humidity) = (
+                cli.simulated_temperature,
+                cli.simulated_pressure,
+                cli.simulated_humidity,
+            );
+            MonitoredSensor::new(
+                spec,
+                move || {
+                    let sensor = SimulatedSensor::new(temperature, pressure, humidity);
+                    Ok(Box::new(sensor) as Box<dyn Sensor>)
+                },
+                &config,
+            )
+        } else {
+            let address = spec.address.unwrap_or(Address::Fixed(0x77));
+            let (spec_owned, sampling) = (spec.clone(), sampling.clone());
+            let raw_adc = config.prometheus.raw_adc;
+            MonitoredSensor::new(
+                spec,
+                move || connect(&spec_owned, address, &sampling, raw_adc),
+                &config,
+            )
+        };
+
+        if let Some(saved) = state.sensors.remove(&spec.name) {
+            sensor.restore(saved);
+        }
+        sensors.push(sensor);
+    }
+
+    let app_state = Arc::new(AppState {
+        prometheus,
+        sensors,
+        sample_on_scrape: config.collection.on_scrape,
+        state_file: config.state.file.clone().map(StateFile::new),
+    });
+
+    if config.collection.on_scrape {
+        info!("measuring on every scrape");
+    } else {
+        let interval = config.collection.interval();
+        info!(?interval, "measuring in the background");
+        tokio::spawn(sample_periodically(app_state.clone(), interval));
+    }
+
+    let mut app = Router::new().route(&config.prometheus.path, get(metrics));
+    // register dumps are for troubleshooting, not for anyone who can reach
+    // the metrics
+    if config.prometheus.debug_endpoint {
+        info!("serving sensor registers on /debug/sensor");
+        app = app.route("/debug/sensor", get(debug_sensor));
+    }
+    let app = app.with_state(app_state);
+
+    axum::Server::bind(&SocketAddr::new(
+        IpAddr::V4(config.listen.host),
+        config.listen.port,
+    ))
+    .serve(app.into_make_service())
+    .await
+    .expect("http server failed");
+}
+
+fn describe_metrics(config: &Config) {
+    metrics::describe_gauge!(
+        "bme280_temperature_celsius",
+        "Temperature in degrees Celsius, split into reading=\"raw\" and reading=\"corrected\" for calibrated sensors"
+    );
+    metrics::describe_gauge!(
+        "bme280_pressure_pascals",
+        "Air pressure in pascals, split into reading=\"raw\" and reading=\"corrected\" for calibrated sensors"
+    );
+    metrics::describe_gauge!(
+        "bme280_relative_humidity_ratio",
+        "Relative humidity as a ratio from 0 to 1, split into reading=\"raw\" and reading=\"corrected\" for calibrated sensors"
+    );
+    if config.prometheus.legacy_metric_names {
+        metrics::describe_gauge!(
+            "temperature",
+            "Temperature in °C, deprecated in favour of bme280_temperature_celsius"
+        );
+        metrics::describe_gauge!(
+            "pressure",
+            "Air pressure in hPa, deprecated in favour of bme280_pressure_pascals"
+        );
+        metrics::describe_gauge!(
+            "humidity",
+            "Relative humidity in %, deprecated in favour of bme280_relative_humidity_ratio"
+        );
+    }
+    if config.prometheus.raw_adc {
+        metrics::describe_gauge!(
+            "bme280_adc_value",
+            "Uncompensated 20 bit temperature and pressure and 16 bit humidity ADC values"
+        );
+    }
+    metrics::describe_gauge!(
+        "bme280_sensor_info",
+        "Chip, bus and sampling configuration of the sensor and the exporter version, 1 for the current connection"
+    );
+    metrics::describe_gauge!("bme280_up", "Whether the last read of the sensor succeeded");
+    metrics::describe_gauge!(
+        "bme280_last_successful_read_timestamp_seconds",
+        "Unix time of the last successful read of the sensor"
+    );
+    metrics::describe_counter!(
+        "bme280_read_errors_total",
+        "Failed sensor reads by the stage they failed in"
+    );
+    metrics::describe_counter!(
+        "bme280_reads_total",
+        "Attempted sensor reads, not counting those skipped during the reconnect backoff"
+    );
+    metrics::describe_counter!(
+        "bme280_rejected_samples_total",
+        "Values dropped for being out of range or clamped for changing too fast"
+    );
+    metrics::describe_gauge!(
+        "bme280_channel_last_updated_timestamp_seconds",
+        "Unix time a channel was last measured, its gauge is NaN while the sensor skips it"
+    );
+    metrics::describe_gauge!(
+        "bme280_sea_level_pressure_pascals",
+        "Air pressure reduced to mean sea level from the station elevation in pascals"
+    );
+    metrics::describe_gauge!(
+        "bme280_altitude_meters",
+        "Altitude estimated from the configured sea-level pressure in meters"
+    );
+    metrics::describe_gauge!(
+        "bme280_pressure_tendency_pascals_per_second",
+        "Air pressure change over the window in pascals per second, 0.0278 Pa/s is 1 hPa/h"
+    );
+    metrics::describe_gauge!(
+        "bme280_pressure_trend",
+        "Air pressure trend over the window, -1 falling, 0 steady and 1 rising"
+    );
+    metrics::describe_gauge!(
+        "bme280_forecast_info",
+        "Zambretti forecast from the sea-level pressure and its 3 hour trend, 1 for the current one, needs the station elevation"
+    );
+    metrics::describe_gauge!(
+        "bme280_heat_index_celsius",
+        "Heat index in degrees Celsius after the NOAA Rothfusz regression"
+    );
+    metrics::describe_gauge!("bme280_humidex", "Humidex as defined by Environment Canada");
+    metrics::describe_gauge!(
+        "bme280_apparent_temperature_celsius",
+        "Apparent temperature in degrees Celsius after the Australian Bureau of Meteorology, without wind"
+    );
+    metrics::describe_gauge!(
+        "bme280_comfort_zone",
+        "Whether the relative humidity is too dry, comfortable or too humid, 1 for the current zone"
+    );
+    metrics::describe_gauge!(
+        "bme280_vapour_pressure_pascals",
+        "Partial pressure of water vapour in pascals"
+    );
+    metrics::describe_gauge!(
+        "bme280_vapour_pressure_deficit_pascals",
+        "Vapour pressure deficit of the air in pascals, 1000 Pa is 1 kPa"
+    );
+    if config.greenhouse.leaf_temperature_offset.is_some() {
+        metrics::describe_gauge!(
+            "bme280_leaf_vapour_pressure_deficit_pascals",
+            "Vapour pressure deficit between the leaf at the configured offset and the air in pascals"
+        );
+    }
+    if config.greenhouse.growing_degree_days.is_some() {
+        metrics::describe_gauge!(
+            "bme280_growing_degree_days",
+            "Degree days (kelvin-days) above the base temperature accumulated since the start of the growing season"
+        );
+    }
+    if config.degree_days.is_some() {
+        metrics::describe_gauge!(
+            "bme280_heating_degree_days",
+            "Degree days (kelvin-days) below the heating base temperature, kept across restarts in the state file"
+        );
+        metrics::describe_gauge!(
+            "bme280_cooling_degree_days",
+            "Degree days (kelvin-days) above the cooling base temperature, kept across restarts in the state file"
+        );
+    }
+    metrics::describe_gauge!(
+        "bme280_dew_point_celsius",
+        "Dew point in degrees Celsius after the Magnus formula"
+    );
+    metrics::describe_gauge!(
+        "bme280_frost_point_celsius",
+        "Frost point in degrees Celsius after the Magnus formula over ice"
+    );
+    metrics::describe_gauge!(
+        "bme280_absolute_humidity_grams_per_cubic_meter",
+        "Mass of water vapour per volume of air in grams per cubic meter"
+    );
+    metrics::describe_gauge!(
+        "bme280_mixing_ratio",
+        "Mass of water vapour per mass of dry air"
+    );
+    metrics::describe_gauge!(
+        "bme280_wet_bulb_temperature_celsius",
+        "Wet-bulb temperature in degrees Celsius after Stull (2011)"
+    );
+    metrics::describe_gauge!(
+        "bme280_enthalpy_joules_per_kilogram",
+        "Specific enthalpy of the moist air in joules per kilogram of dry air"
+    );
+}
+
+fn connect(
+    spec: &SensorSpec,
+    address: Address,
+    settings: &SamplingSettings,
+    raw_adc: bool,
+) -> anyhow::Result<Box<dyn Sensor>> {
+    let mut sensor = match &spec.device {
+        Bus::I2c(path) => open_i2c(spec, path, address, settings, raw_adc)?,
+        Bus::Spi(path) => {
+            info!(
+                sensor = spec.name,
+                spi_device_path = path.display().to_string(),
+                "connecting to spi bus",
+            );
+            // the address only selects the sensor on an i2c bus
+            sensor::open(sensor::spi::open(path)?, 0, Delay, settings, raw_adc)?
+        }
+    };
+    sensor.setup().context("failed to setup sensor")?;
+
+    Ok(sensor)
+}
 
+fn open_i2c(
+    spec: &SensorSpec,
+    path: &Path,
+    address: Address,
+    settings: &SamplingSettings,
+    raw_adc: bool,
+) -> anyhow::Result<Box<dyn Sensor>> {
     info!(
-        i2c_device_path = cli.i2c_device_path.display().to_string(),
+        sensor = spec.name,
+        i2c_device_path = path.display().to_string(),
         "connecting to i2c bus",
     );
-    let i2c_bus = I2cdev::new(cli.i2c_device_path).expect("failed to setup i2c bus");
-    let mut bme280 = Bme280::new_with_address(i2c_bus, 0x77, Delay);
-
-    info!("initializing bme280 sensor");
-    bme280.init().expect("failed to setup bme280 sensor");
-
-    info!("configuring bme280 sensor");
-    bme280
-        .set_sampling_configuration(
-            Configuration::default()
-                .with_filter(bme280_rs::Filter::Filter4)
-                .with_temperature_oversampling(Oversampling::Oversample8)
-                .with_pressure_oversampling(Oversampling::Oversample8)
-                .with_humidity_oversampling(Oversampling::Oversample8)
-                .with_sensor_mode(bme280_rs::SensorMode::Forced),
-        )
-        .expect("failed to configure bme280 sensor");
-
-    let app_state = AppState {
-        prometheus,
-        bme280: Mutex::new(bme280),
+    let mut i2c_bus =
+        I2cdev::new(path).with_context(|| format!("failed to open i2c bus {}", path.display()))?;
+
+    let address = match address {
+        Address::Fixed(address) => address,
+        Address::Auto => {
+            let address = sensor::probe_address(&mut i2c_bus)
+                .context("no bme280 or bmp280 sensor found at 0x76 or 0x77")?;
+            info!(
+                sensor = spec.name,
+                address = format!("{address:#04x}"),
+                "found sensor",
+            );
+            address
+        }
     };
 
-    let app = Router::new()
-        .route("/metrics", get(metrics))
-        .with_state(Arc::new(app_state));
+    sensor::open(i2c_bus, address, Delay, settings, raw_adc)
+}
+
+async fn sample_periodically(app_state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // a slow bus should stretch the interval, not cause a burst of catch-up reads
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-    axum::Server::bind(&SocketAddr::new(IpAddr::V4(cli.host), cli.port))
-        .serve(app.into_make_service())
-        .await
-        .expect("http server failed");
+    loop {
+        ticker.tick().await;
+        app_state.sample().await;
+    }
 }
 
-async fn metrics(State(app_state): State<Arc<AppState>>) -> Result<String, AppError> {
-    let mut bme280 = app_state.bme280.lock().await;
+async fn metrics(State(app_state): State<Arc<AppState>>) -> String {
+    // sensor failures are reported through bme280_up and the error counters,
+    // failing the scrape would hide them
+    if app_state.sample_on_scrape {
+        app_state.sample().await;
+    }
 
-    bme280.take_forced_measurement()?;
-    let (temperature, pressure, humidity) = bme280.read_sample()?;
+    app_state.prometheus.render()
+}
 
-    if let Some(temperature) = temperature {
-        metrics::gauge!("temperature", f64::from(temperature));
+/// Trimming parameters and registers of every sensor, to tell apart a faulty
+/// chip, bad trimming and a compensation bug
+async fn debug_sensor(
+    State(app_state): State<Arc<AppState>>,
+) -> Json<BTreeMap<String, serde_json::Value>> {
+    let mut sensors = BTreeMap::new();
+    for sensor in &app_state.sensors {
+        let inspection = match sensor.inspect().await {
+            Ok(Some(inspection)) => serde_json::to_value(inspection),
+            Ok(None) => Ok(serde_json::json!({ "error": "sensor has no registers" })),
+            Err(failure) => Ok(serde_json::json!({ "error": failure.to_string() })),
+        };
+        sensors.insert(
+            sensor.name.clone(),
+            inspection.expect("inspection is serializable"),
+        );
     }
 
-    if let Some(pressure) = pressure {
-        metrics::gauge!("pressure", f64::from(pressure / 100.0));
+    Json(sensors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::GreenhouseConfig,
+        degree_days::{Accumulator, GrowingSeason},
+        monitored::unix_time,
+        sensor::fake::{Calibration, FakeBme280, NoopDelay, REGISTER_CTRL_HUM},
+        state::SensorState,
+    };
+    use embedded_hal::blocking::i2c::Write;
+    use std::sync::OnceLock;
+
+    pub(crate) fn prometheus() -> PrometheusHandle {
+        static PROMETHEUS: OnceLock<PrometheusHandle> = OnceLock::new();
+
+        PROMETHEUS
+            .get_or_init(|| {
+                PrometheusBuilder::new()
+                    .install_recorder()
+                    .expect("failed to setup prometheus metrics")
+            })
+            .clone()
     }
 
-    if let Some(humidity) = humidity {
-        metrics::gauge!("humidity", f64::from(humidity));
+    fn sample(rendered: &str, name: &str) -> f64 {
+        rendered
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("{name} missing from:\n{rendered}"))
+            .parse()
+            .expect("sample value is not a number")
     }
 
-    Ok(app_state.prometheus.render())
-}
+    pub(crate) fn spec(name: &str, location: Option<&str>) -> SensorSpec {
+        // sensors register their health series when they are created, which
+        // is lost without a recorder
+        prometheus();
+        SensorSpec {
+            location: location.map(str::to_owned),
+            ..SensorSpec::new(name, Bus::I2c(PathBuf::from("/dev/i2c-fake")))
+        }
+    }
 
-struct AppError(anyhow::Error);
+    pub(crate) fn fake_sensor(
+        device: &FakeBme280,
+        address: u8,
+    ) -> impl FnMut() -> anyhow::Result<Box<dyn Sensor>> + Send + 'static {
+        let device = device.clone();
+        move || {
+            // always read the raw adc values, the tests decide on exporting
+            // them through the config
+            let mut sensor = sensor::open(
+                device.clone(),
+                address,
+                NoopDelay,
+                &SamplingSettings::default(),
+                true,
+            )?;
+            sensor.setup()?;
+            Ok(sensor)
+        }
+    }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("kapot: {:?}", self.0),
-        )
-            .into_response()
+    /// The datasheet's compensation example, answering at 0x77
+    pub(crate) fn datasheet_bme280() -> FakeBme280 {
+        let device = FakeBme280::new(0x77, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        device
     }
-}
 
-impl<E> From<E> for AppError
-where
-    E: Into<anyhow::Error>,
-{
-    fn from(err: E) -> Self {
-        Self(err.into())
+    /// App that measures on every scrape, without a state file
+    fn app_with_sensors(sensors: Vec<MonitoredSensor>) -> Arc<AppState> {
+        Arc::new(AppState {
+            prometheus: prometheus(),
+            sensors,
+            sample_on_scrape: true,
+            state_file: None,
+        })
+    }
+
+    /// App with a single sensor, read from `device` at 0x77
+    fn app_with(name: &str, device: &FakeBme280, config: &Config) -> Arc<AppState> {
+        app_with_sensors(vec![MonitoredSensor::new(
+            &spec(name, None),
+            fake_sensor(device, 0x77),
+            config,
+        )])
+    }
+
+    #[test]
+    fn address_only_applies_to_i2c_sensors() {
+        let load = |args: &[&str]| {
+            Cli::try_parse_from([&["bme280-exporter"], args].concat())
+                .expect("failed to parse arguments")
+                .load_config()
+        };
+
+        let config =
+            load(&["--bus", "/dev/i2c-1", "--address", "0x76"]).expect("i2c bus takes an address");
+        assert_eq!(config.sensors[0].address, Some(Address::Fixed(0x76)));
+        assert!(load(&["--bus", "spi:/dev/spidev0.0", "--address", "0x76"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn metrics_renders_compensated_datasheet_vector() {
+        let device = datasheet_bme280();
+
+        let app_state = app_with_sensors(vec![MonitoredSensor::new(
+            &spec("datasheet", Some("lab")),
+            fake_sensor(&device, 0x77),
+            &Config::default(),
+        )]);
+
+        let rendered = metrics(State(app_state)).await;
+
+        let labels = r#"{sensor="datasheet",location="lab"}"#;
+        let temperature = sample(&rendered, &format!("bme280_temperature_celsius{labels}"));
+        assert!((temperature - 25.08).abs() < 0.005);
+        let pressure = sample(&rendered, &format!("bme280_pressure_pascals{labels}"));
+        assert!((pressure - 100_653.27).abs() < 0.05);
+        let humidity = sample(
+            &rendered,
+            &format!("bme280_relative_humidity_ratio{labels}"),
+        );
+        assert!((humidity - 0.5196).abs() < 0.0001);
+        assert!(!rendered.contains(&format!("temperature{labels}")));
+        let dew_point = sample(&rendered, &format!("bme280_dew_point_celsius{labels}"));
+        assert!((dew_point - 14.52).abs() < 0.01, "dew point {dew_point}");
+        assert_eq!(device.conversions(), 2);
+    }
+
+    #[tokio::test]
+    async fn metrics_reduces_pressure_to_sea_level() {
+        let device = datasheet_bme280();
+        let mut config = Config::default();
+        config.station.elevation = Some(100.0);
+        config.station.qnh = Some(1020.0);
+
+        let app_state = app_with("station", &device, &config);
+
+        let rendered = metrics(State(app_state)).await;
+
+        let pressure = sample(
+            &rendered,
+            r#"bme280_sea_level_pressure_pascals{sensor="station"}"#,
+        );
+        assert!((pressure - 101_811.9).abs() < 0.5, "pressure {pressure}");
+        let altitude = sample(&rendered, r#"bme280_altitude_meters{sensor="station"}"#);
+        assert!((altitude - 116.15).abs() < 0.1, "altitude {altitude}");
+    }
+
+    #[tokio::test]
+    async fn metrics_classifies_comfort_zone() {
+        let device = datasheet_bme280();
+        let config = Config {
+            comfort: Some(ComfortConfig {
+                min_humidity: 30.0,
+                max_humidity: 50.0,
+            }),
+            ..Config::default()
+        };
+
+        let app_state = app_with("office", &device, &config);
+
+        let rendered = metrics(State(app_state)).await;
+
+        let zone = r#"bme280_comfort_zone{sensor="office",zone="#;
+        assert_eq!(sample(&rendered, &format!(r#"{zone}"too_dry"}}"#)), 0.0);
+        assert_eq!(sample(&rendered, &format!(r#"{zone}"comfortable"}}"#)), 0.0);
+        assert_eq!(sample(&rendered, &format!(r#"{zone}"too_humid"}}"#)), 1.0);
+        let humidex = sample(&rendered, r#"bme280_humidex{sensor="office"}"#);
+        assert!((humidex - 28.76).abs() < 0.01, "humidex {humidex}");
+    }
+
+    #[tokio::test]
+    async fn metrics_accumulates_growing_degree_days_from_saved_state() {
+        let device = datasheet_bme280();
+        let config = Config {
+            greenhouse: GreenhouseConfig {
+                leaf_temperature_offset: Some(-2.0),
+                growing_degree_days: Some(GrowingDegreeDaysConfig::default()),
+            },
+            ..Config::default()
+        };
+        let sensor = MonitoredSensor::new(
+            &spec("greenhouse", None),
+            fake_sensor(&device, 0x77),
+            &config,
+        );
+        let now = unix_time();
+        let mut season = GrowingSeason::default();
+        // a minute ago, 100 degree days into the current season
+        season.add(now - 60.0, 10.0, 10.0, SeasonStart::default());
+        season.accumulator.degree_seconds = 100.0 * 86_400.0;
+        sensor.restore(SensorState {
+            growing_season: Some(season),
+            ..SensorState::default()
+        });
+
+        let app_state = app_with_sensors(vec![sensor]);
+
+        let rendered = metrics(State(app_state)).await;
+
+        let vpd = sample(
+            &rendered,
+            r#"bme280_vapour_pressure_deficit_pascals{sensor="greenhouse"}"#,
+        );
+        assert!((vpd - 1526.2).abs() < 0.5, "vpd {vpd}");
+        let leaf_vpd = sample(
+            &rendered,
+            r#"bme280_leaf_vapour_pressure_deficit_pascals{sensor="greenhouse"}"#,
+        );
+        assert!((leaf_vpd - 1166.7).abs() < 0.5, "leaf vpd {leaf_vpd}");
+        // 15.08 degrees above the base for a minute
+        let degree_days = sample(
+            &rendered,
+            r#"bme280_growing_degree_days{sensor="greenhouse"}"#,
+        );
+        assert!(
+            (degree_days - 100.0105).abs() < 0.001,
+            "degree days {degree_days}"
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_accumulates_heating_and_cooling_degree_days() {
+        let device = datasheet_bme280();
+        let config = Config {
+            degree_days: Some(DegreeDaysConfig {
+                heating_base_temperature: 30.0,
+                cooling_base_temperature: 20.0,
+            }),
+            ..Config::default()
+        };
+        let sensor =
+            MonitoredSensor::new(&spec("energy", None), fake_sensor(&device, 0x77), &config);
+        let updated = Some(unix_time() - 100.0);
+        sensor.restore(SensorState {
+            heating: Accumulator {
+                degree_seconds: 86_400.0,
+                updated,
+            },
+            cooling: Accumulator {
+                degree_seconds: 0.0,
+                updated,
+            },
+            ..SensorState::default()
+        });
+
+        let app_state = app_with_sensors(vec![sensor]);
+
+        let rendered = metrics(State(app_state)).await;
+
+        // 25.08 °C is 4.92 K below the heating and 5.08 K above the cooling
+        // base, for the 100 s since the restored sample
+        let heating = sample(&rendered, r#"bme280_heating_degree_days{sensor="energy"}"#);
+        assert!((heating - 1.005_694).abs() < 0.000_03, "heating {heating}");
+        let cooling = sample(&rendered, r#"bme280_cooling_degree_days{sensor="energy"}"#);
+        assert!((cooling - 0.005_880).abs() < 0.000_03, "cooling {cooling}");
+    }
+
+    #[tokio::test]
+    async fn metrics_renders_raw_and_calibrated_readings() {
+        let device = datasheet_bme280();
+        let spec = SensorSpec {
+            calibration: toml::from_str(
+                r#"
+                temperature = { offset = -2.08 }
+                pressure = { offset = 500 }
+                "#,
+            )
+            .expect("failed to parse calibration"),
+            ..spec("calibrated", None)
+        };
+
+        let app_state = app_with_sensors(vec![MonitoredSensor::new(
+            &spec,
+            fake_sensor(&device, 0x77),
+            &Config::default(),
+        )]);
+
+        let rendered = metrics(State(app_state)).await;
+
+        let raw = r#"{sensor="calibrated",reading="raw"}"#;
+        let corrected = r#"{sensor="calibrated",reading="corrected"}"#;
+        let temperature = sample(&rendered, &format!("bme280_temperature_celsius{raw}"));
+        assert!((temperature - 25.08).abs() < 0.005);
+        let temperature = sample(&rendered, &format!("bme280_temperature_celsius{corrected}"));
+        assert!((temperature - 23.0).abs() < 0.005);
+        let pressure = sample(&rendered, &format!("bme280_pressure_pascals{corrected}"));
+        assert!((pressure - 101_153.27).abs() < 0.05);
+        let humidity = sample(&rendered, &format!("bme280_relative_humidity_ratio{raw}"));
+        assert!((humidity - 0.5196).abs() < 0.0001);
+        // the same vapour pressure at the corrected temperature
+        let humidity = sample(
+            &rendered,
+            &format!("bme280_relative_humidity_ratio{corrected}"),
+        );
+        assert!((humidity - 0.5887).abs() < 0.0005, "humidity {humidity}");
+        let dew_point = sample(
+            &rendered,
+            r#"bme280_dew_point_celsius{sensor="calibrated"}"#,
+        );
+        assert!((dew_point - 14.52).abs() < 0.01, "dew point {dew_point}");
+        // no unlabelled series to mix up with the raw one
+        assert!(!rendered.contains(r#"bme280_temperature_celsius{sensor="calibrated"}"#));
+    }
+
+    #[tokio::test]
+    async fn metrics_counts_rejected_samples() {
+        let device = datasheet_bme280();
+        let mut config = Config::default();
+        config.processing.temperature.max_rate = Some(0.001);
+
+        let app_state = app_with("glitchy", &device, &config);
+
+        metrics(State(app_state.clone())).await;
+        // a warmer temperature that changes faster than allowed
+        device.set_adc(529_888, 415_148, 30_000);
+        let rendered = metrics(State(app_state)).await;
+
+        let temperature = sample(&rendered, r#"bme280_temperature_celsius{sensor="glitchy"}"#);
+        assert!(
+            (temperature - 25.08).abs() < 0.01,
+            "temperature {temperature}"
+        );
+        let rejected = r#"bme280_rejected_samples_total{sensor="glitchy",channel="#;
+        assert_eq!(
+            sample(
+                &rendered,
+                &format!(r#"{rejected}"temperature",reason="rate_limited"}}"#)
+            ),
+            1.0
+        );
+        assert_eq!(
+            sample(
+                &rendered,
+                &format!(r#"{rejected}"humidity",reason="out_of_range"}}"#)
+            ),
+            0.0
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_exports_raw_adc_values_when_asked() {
+        let device = datasheet_bme280();
+        let mut config = Config::default();
+        config.prometheus.raw_adc = true;
+
+        let app_state = app_with("adc", &device, &config);
+
+        let rendered = metrics(State(app_state)).await;
+
+        let adc = r#"bme280_adc_value{sensor="adc",channel="#;
+        assert_eq!(
+            sample(&rendered, &format!(r#"{adc}"temperature"}}"#)),
+            519_888.0
+        );
+        assert_eq!(
+            sample(&rendered, &format!(r#"{adc}"pressure"}}"#)),
+            415_148.0
+        );
+        assert_eq!(
+            sample(&rendered, &format!(r#"{adc}"humidity"}}"#)),
+            30_000.0
+        );
+    }
+
+    #[tokio::test]
+    async fn debug_sensor_dumps_registers() {
+        let device = FakeBme280::new(0x77, Calibration::DATASHEET);
+        let app_state = app_with_sensors(vec![
+            MonitoredSensor::new(
+                &spec("inspected", None),
+                fake_sensor(&device, 0x77),
+                &Config::default(),
+            ),
+            MonitoredSensor::new(
+                &spec("simulated", None),
+                || Ok(Box::new(SimulatedSensor::new(21.0, 101_325.0, 45.0)) as Box<dyn Sensor>),
+                &Config::default(),
+            ),
+        ]);
+
+        let Json(sensors) = debug_sensor(State(app_state)).await;
+
+        let inspected = &sensors["inspected"];
+        assert_eq!(inspected["chip_id"], 0x60);
+        assert_eq!(inspected["address"], 0x77);
+        assert_eq!(inspected["trimming"]["dig_t1"], 27504);
+        assert_eq!(inspected["trimming"]["dig_p9"], 6000);
+        assert_eq!(inspected["trimming"]["dig_h2"], 362);
+        // osrs_t = x8, osrs_p = x8, sleep
+        assert_eq!(inspected["registers"]["ctrl_meas"], 0b100 << 5 | 0b100 << 2);
+        assert_eq!(sensors["simulated"]["error"], "sensor has no registers");
+    }
+
+    #[tokio::test]
+    async fn metrics_describes_connected_sensor() {
+        let device = datasheet_bme280();
+
+        let app_state = app_with("described", &device, &Config::default());
+
+        let rendered = metrics(State(app_state)).await;
+
+        let info = format!(
+            concat!(
+                r#"bme280_sensor_info{{sensor="described",variant="bme280",chip_id="0x60","#,
+                r#"bus="/dev/i2c-fake","#,
+                r#"address="0x77",temperature_oversampling="8",pressure_oversampling="8","#,
+                r#"humidity_oversampling="8",filter="4",mode="forced",version="{}"}}"#
+            ),
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(sample(&rendered, &info), 1.0);
+    }
+
+    #[tokio::test]
+    async fn metrics_leaves_out_humidity_for_bmp280() {
+        let device = FakeBme280::with_chip_id(0x77, 0x58, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        let mut config = Config::default();
+        config.prometheus.legacy_metric_names = true;
+        config.prometheus.raw_adc = true;
+
+        let app_state = app_with("bmp", &device, &config);
+
+        let rendered = metrics(State(app_state)).await;
+
+        let temperature = sample(&rendered, r#"bme280_temperature_celsius{sensor="bmp"}"#);
+        assert!((temperature - 25.08).abs() < 0.005);
+        let pressure = sample(&rendered, r#"bme280_pressure_pascals{sensor="bmp"}"#);
+        assert!((pressure - 100_653.27).abs() < 0.05);
+        let bmp = r#"{sensor="bmp""#;
+        for name in [
+            "bme280_relative_humidity_ratio",
+            "humidity",
+            "bme280_dew_point_celsius",
+            "bme280_heat_index_celsius",
+        ] {
+            assert!(
+                !rendered.contains(&format!("\n{name}{bmp}")),
+                "{name} exported for a bmp280"
+            );
+        }
+        assert!(!rendered.contains(r#"bme280_adc_value{sensor="bmp",channel="humidity"}"#));
+        assert!(
+            !rendered.contains(r#"bme280_rejected_samples_total{sensor="bmp",channel="humidity""#)
+        );
+        assert!(rendered
+            .contains(r#"bme280_sensor_info{sensor="bmp",variant="bmp280",chip_id="0x58","#));
+        assert!(rendered.contains(r#"humidity_oversampling="skip""#));
+    }
+
+    #[tokio::test]
+    async fn metrics_keeps_legacy_names_when_asked() {
+        let device = datasheet_bme280();
+        let mut config = Config::default();
+        config.prometheus.legacy_metric_names = true;
+
+        let app_state = app_with("legacy", &device, &config);
+
+        let rendered = metrics(State(app_state)).await;
+
+        let temperature = sample(&rendered, r#"temperature{sensor="legacy"}"#);
+        assert!((temperature - 25.08).abs() < 0.005);
+        let pressure = sample(&rendered, r#"pressure{sensor="legacy"}"#);
+        assert!((pressure - 1006.5327).abs() < 0.001);
+        let humidity = sample(&rendered, r#"humidity{sensor="legacy"}"#);
+        assert!((humidity - 51.96).abs() < 0.01);
+        let pressure = sample(&rendered, r#"bme280_pressure_pascals{sensor="legacy"}"#);
+        assert!((pressure - 100_653.27).abs() < 0.05);
+    }
+
+    #[tokio::test]
+    async fn metrics_survives_a_failing_sensor() {
+        let working = FakeBme280::new(0x76, Calibration::DATASHEET);
+        working.set_adc(519_888, 415_148, 30_000);
+        let unplugged = FakeBme280::new(0x76, Calibration::DATASHEET);
+
+        let app_state = app_with_sensors(vec![
+            MonitoredSensor::new(
+                &spec("unplugged", None),
+                fake_sensor(&unplugged, 0x77),
+                &Config::default(),
+            ),
+            MonitoredSensor::new(
+                &spec("working", None),
+                fake_sensor(&working, 0x76),
+                &Config::default(),
+            ),
+        ]);
+
+        let rendered = metrics(State(app_state)).await;
+
+        let temperature = sample(&rendered, r#"bme280_temperature_celsius{sensor="working"}"#);
+        assert!((temperature - 25.08).abs() < 0.005);
+        assert_eq!(sample(&rendered, r#"bme280_up{sensor="working"}"#), 1.0);
+        assert_eq!(sample(&rendered, r#"bme280_up{sensor="unplugged"}"#), 0.0);
+        assert!(!rendered.contains(r#"bme280_temperature_celsius{sensor="unplugged"}"#));
+    }
+
+    #[tokio::test]
+    async fn metrics_reports_sensor_that_fails() {
+        let unplugged = FakeBme280::new(0x76, Calibration::DATASHEET);
+
+        let app_state = app_with("gone", &unplugged, &Config::default());
+
+        metrics(State(app_state.clone())).await;
+        // the second scrape falls into the backoff and does not touch the bus
+        let rendered = metrics(State(app_state)).await;
+
+        assert_eq!(sample(&rendered, r#"bme280_up{sensor="gone"}"#), 0.0);
+        assert_eq!(
+            sample(&rendered, r#"bme280_reads_total{sensor="gone"}"#),
+            1.0
+        );
+        let errors = r#"bme280_read_errors_total{sensor="gone",stage="#;
+        assert_eq!(sample(&rendered, &format!(r#"{errors}"measure"}}"#)), 0.0);
+        assert_eq!(sample(&rendered, &format!(r#"{errors}"read"}}"#)), 0.0);
+        assert_eq!(sample(&rendered, &format!(r#"{errors}"init"}}"#)), 1.0);
+    }
+
+    #[tokio::test]
+    async fn overlapping_scrapes_keep_the_sensor_up() {
+        let device = datasheet_bme280();
+        let app_state = app_with("shared", &device, &Config::default());
+
+        let (_, _, Json(sensors)) = tokio::join!(
+            metrics(State(app_state.clone())),
+            metrics(State(app_state.clone())),
+            debug_sensor(State(app_state.clone())),
+        );
+        assert_eq!(sensors["shared"]["chip_id"], 0x60);
+        let rendered = metrics(State(app_state)).await;
+
+        assert_eq!(sample(&rendered, r#"bme280_up{sensor="shared"}"#), 1.0);
+        let errors = r#"bme280_read_errors_total{sensor="shared",stage="#;
+        assert_eq!(sample(&rendered, &format!(r#"{errors}"measure"}}"#)), 0.0);
+        assert_eq!(
+            sample(&rendered, r#"bme280_reads_total{sensor="shared"}"#),
+            3.0
+        );
+    }
+
+    #[tokio::test]
+    async fn unplugged_spi_sensor_goes_down_and_comes_back() {
+        let device = datasheet_bme280();
+        let spi_device = device.clone();
+        let spec = SensorSpec::new("spi", Bus::Spi(PathBuf::from("/dev/spidev-fake")));
+        let app_state = app_with_sensors(vec![MonitoredSensor::new(
+            &spec,
+            move || {
+                let spi = sensor::spi::SpiInterface::new(spi_device.spi());
+                let mut sensor =
+                    sensor::open(spi, 0, NoopDelay, &SamplingSettings::default(), false)?;
+                sensor.setup()?;
+                Ok(sensor)
+            },
+            &Config::default(),
+        )]);
+        let rendered = metrics(State(app_state.clone())).await;
+        assert_eq!(sample(&rendered, r#"bme280_up{sensor="spi"}"#), 1.0);
+
+        // all ones on the data line, enough failed reads for a re-initialisation
+        device.unplug();
+        let mut rendered = String::new();
+        for _ in 0..3 {
+            rendered = metrics(State(app_state.clone())).await;
+        }
+        assert_eq!(sample(&rendered, r#"bme280_up{sensor="spi"}"#), 0.0);
+        let errors = r#"bme280_read_errors_total{sensor="spi",stage="#;
+        assert_eq!(sample(&rendered, &format!(r#"{errors}"measure"}}"#)), 3.0);
+        // the setup failed as well, so the chip is no longer known
+        let info = rendered
+            .lines()
+            .find(|line| line.starts_with(r#"bme280_sensor_info{sensor="spi","#))
+            .expect("sensor info missing");
+        assert!(info.ends_with(" 0"), "{info}");
+
+        // the next connect after the backoff sets the sensor up again
+        device.plug_in();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let rendered = metrics(State(app_state)).await;
+        assert_eq!(sample(&rendered, r#"bme280_up{sensor="spi"}"#), 1.0);
+        let temperature = sample(&rendered, r#"bme280_temperature_celsius{sensor="spi"}"#);
+        assert!((temperature - 25.08).abs() < 0.005);
+        assert_eq!(device.register(REGISTER_CTRL_HUM), 0b100);
+    }
+
+    #[tokio::test]
+    async fn sample_saves_state_in_the_background() {
+        let path = std::env::temp_dir().join(format!("bme280-app-{}.json", std::process::id()));
+        let mut app_state = app_with("saved", &datasheet_bme280(), &Config::default());
+        Arc::get_mut(&mut app_state)
+            .expect("app state is not shared yet")
+            .state_file = Some(StateFile::new(path.clone()));
+
+        app_state.sample().await;
+        let state_file = app_state.state_file.as_ref().expect("state file missing");
+        let saving = state_file
+            .saving
+            .lock()
+            .expect("state file poisoned")
+            .take();
+        saving
+            .expect("no save started")
+            .await
+            .expect("save panicked");
+        let state = PersistentState::load(&path).expect("failed to load state");
+        std::fs::remove_file(&path).expect("failed to remove state");
+
+        assert!(state.sensors.contains_key("saved"));
+    }
+
+    #[tokio::test]
+    async fn metrics_renders_background_sample_without_measuring() {
+        let device = datasheet_bme280();
+
+        let mut app_state = app_with("cached", &device, &Config::default());
+        Arc::get_mut(&mut app_state)
+            .expect("app state is not shared yet")
+            .sample_on_scrape = false;
+        app_state.sample().await;
+
+        for _ in 0..3 {
+            let rendered = metrics(State(app_state.clone())).await;
+
+            let temperature = sample(&rendered, r#"bme280_temperature_celsius{sensor="cached"}"#);
+            assert!((temperature - 25.08).abs() < 0.005);
+        }
+        // one conversion from setup, one from the background sample
+        assert_eq!(device.conversions(), 2);
+    }
+
+    #[tokio::test]
+    async fn metrics_marks_skipped_channel_stale() {
+        let device = datasheet_bme280();
+
+        let app_state = app_with("stale", &device, &Config::default());
+
+        let rendered = metrics(State(app_state.clone())).await;
+        let humidity = r#"bme280_relative_humidity_ratio{sensor="stale"}"#;
+        assert!((sample(&rendered, humidity) - 0.5196).abs() < 0.0001);
+        let updated =
+            r#"bme280_channel_last_updated_timestamp_seconds{sensor="stale",channel="humidity"}"#;
+        let humidity_updated = sample(&rendered, updated);
+
+        // the chip stops measuring humidity, for instance after a glitch
+        device
+            .clone()
+            .write(0x77, &[REGISTER_CTRL_HUM, 0])
+            .expect("failed to write ctrl_hum");
+
+        let rendered = metrics(State(app_state)).await;
+        assert!(sample(&rendered, humidity).is_nan());
+        assert_eq!(sample(&rendered, updated), humidity_updated);
+        let temperature = r#"bme280_temperature_celsius{sensor="stale"}"#;
+        assert!((sample(&rendered, temperature) - 25.08).abs() < 0.005);
     }
 }
@@ -0,0 +1,612 @@
+//! A configured sensor and the metrics recorded from its readings
+
+use crate::{
+    atmosphere,
+    calibration::Calibration,
+    comfort::{self, Zone},
+    config::{ComfortConfig, Config, DegreeDaysConfig, GreenhouseConfig, StationConfig},
+    forecast::{self, Forecast, PressureHistory, Trend},
+    processing::{self, Processor, Rejection},
+    psychrometrics,
+    sensor::{Bus, Inspection, Reading, Sensor, SensorInfo, SensorSpec},
+    state::SensorState,
+    worker::{Failure, SensorWorker, Stage},
+};
+use metrics::{Label, SharedString};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+pub struct MonitoredSensor {
+    pub name: String,
+    labels: Vec<Label>,
+    bus: Bus,
+    info: std::sync::Mutex<Option<SensorInfo>>,
+    worker: SensorWorker,
+    calibration: Option<Calibration>,
+    processor: std::sync::Mutex<Processor>,
+    legacy_metric_names: bool,
+    raw_adc: bool,
+    station: StationConfig,
+    comfort: Option<ComfortConfig>,
+    greenhouse: GreenhouseConfig,
+    degree_days: Option<DegreeDaysConfig>,
+    state: std::sync::Mutex<SensorState>,
+    pressure_history: std::sync::Mutex<PressureHistory>,
+    forecast: std::sync::Mutex<Option<Forecast>>,
+    /// Humidity series are registered on the first reading that has them
+    humidity_series: std::sync::Once,
+}
+
+impl MonitoredSensor {
+    pub fn new<C>(spec: &SensorSpec, connect: C, config: &Config) -> Self
+    where
+        C: FnMut() -> anyhow::Result<Box<dyn Sensor>> + Send + 'static,
+    {
+        let mut labels = vec![Label::new("sensor", spec.name.clone())];
+        if let Some(location) = &spec.location {
+            labels.push(Label::new("location", location.clone()));
+        }
+
+        let monitored = Self {
+            name: spec.name.clone(),
+            labels,
+            bus: spec.device.clone(),
+            info: Default::default(),
+            worker: SensorWorker::spawn(&spec.name, connect, config.collection.timeout()),
+            calibration: spec.calibration,
+            processor: std::sync::Mutex::new(Processor::new(&config.processing)),
+            legacy_metric_names: config.prometheus.legacy_metric_names,
+            raw_adc: config.prometheus.raw_adc,
+            station: config.station,
+            comfort: config.comfort,
+            greenhouse: config.greenhouse,
+            degree_days: config.degree_days,
+            state: Default::default(),
+            pressure_history: Default::default(),
+            forecast: Default::default(),
+            humidity_series: std::sync::Once::new(),
+        };
+
+        // export the health series before the first read, so a sensor that
+        // never worked shows up as down instead of missing
+        metrics::gauge!("bme280_up", 0.0, monitored.labels.iter());
+        metrics::counter!("bme280_reads_total", 0, monitored.labels.iter());
+        for stage in Stage::ALL {
+            metrics::counter!(
+                "bme280_read_errors_total",
+                0,
+                monitored.labels_with("stage", stage.as_str())
+            );
+        }
+        // humidity waits for a reading that tells whether the chip has it
+        for channel in processing::CHANNELS {
+            if channel != "humidity" {
+                monitored.register_rejections(channel);
+            }
+        }
+
+        monitored
+    }
+
+    fn register_rejections(&self, channel: &'static str) {
+        for rejection in Rejection::ALL {
+            metrics::counter!(
+                "bme280_rejected_samples_total",
+                0,
+                self.rejection_labels(channel, rejection)
+            );
+        }
+    }
+
+    /// Continue from the accumulated values of an earlier run
+    pub fn restore(&self, state: SensorState) {
+        *self.state.lock().expect("sensor state poisoned") = state;
+    }
+
+    pub fn state(&self) -> SensorState {
+        self.state.lock().expect("sensor state poisoned").clone()
+    }
+
+    /// Trimming parameters and registers of the connected chip
+    pub async fn inspect(&self) -> Result<Option<Inspection>, Failure> {
+        self.worker.inspect().await
+    }
+
+    fn rejection_labels(&self, channel: &'static str, rejection: Rejection) -> Vec<Label> {
+        let mut labels = self.labels_with("channel", channel);
+        labels.push(Label::new("reason", rejection.as_str()));
+        labels
+    }
+
+    fn labels_with(&self, key: &'static str, value: impl Into<SharedString>) -> Vec<Label> {
+        let mut labels = self.labels.clone();
+        labels.push(Label::new(key, value));
+        labels
+    }
+
+    pub async fn measure(&self) {
+        let result = self.worker.measure().await;
+        if let Err(Failure::Busy) = result {
+            // the overlapping request already reported on the sensor
+            warn!(
+                sensor = self.name,
+                "skipped a read while the sensor was busy"
+            );
+            return;
+        }
+        // after a reconnect the info tells which channels the chip has
+        self.record_info();
+
+        match result {
+            Ok(reading) => {
+                metrics::increment_counter!("bme280_reads_total", self.labels.iter());
+                metrics::gauge!("bme280_up", 1.0, self.labels.iter());
+                metrics::gauge!(
+                    "bme280_last_successful_read_timestamp_seconds",
+                    unix_time(),
+                    self.labels.iter()
+                );
+                self.record(reading);
+            }
+            Err(failure) => {
+                warn!(sensor = self.name, error = %failure, "failed to read sensor");
+                metrics::gauge!("bme280_up", 0.0, self.labels.iter());
+                // a sensor in backoff is down, but was not read
+                if let Some(stage) = failure.stage() {
+                    metrics::increment_counter!("bme280_reads_total", self.labels.iter());
+                    metrics::increment_counter!(
+                        "bme280_read_errors_total",
+                        self.labels_with("stage", stage.as_str())
+                    );
+                }
+            }
+        }
+    }
+
+    /// The sensor may come back with another chip or address after a
+    /// reconnect, so the info of the previous connection is set to 0
+    fn record_info(&self) {
+        let info = self.worker.info();
+        let mut current = self.info.lock().expect("sensor info poisoned");
+        if *current == info {
+            return;
+        }
+
+        if let Some(previous) = current.take() {
+            metrics::gauge!("bme280_sensor_info", 0.0, self.info_labels(&previous));
+        }
+        if let Some(info) = info {
+            metrics::gauge!("bme280_sensor_info", 1.0, self.info_labels(&info));
+            *current = Some(info);
+        }
+    }
+
+    fn info_labels(&self, info: &SensorInfo) -> Vec<Label> {
+        let settings = &info.settings;
+        let address = match self.bus {
+            Bus::I2c(_) => format!("{:#04x}", info.address),
+            // the chip select picks the sensor
+            Bus::Spi(_) => String::new(),
+        };
+        let mut labels = self.labels_with("variant", info.variant.as_str());
+        labels.extend([
+            Label::new("chip_id", format!("{:#04x}", info.chip_id)),
+            Label::new("bus", self.bus.to_string()),
+            Label::new("address", address),
+            Label::new(
+                "temperature_oversampling",
+                settings.temperature_oversampling.to_string(),
+            ),
+            Label::new(
+                "pressure_oversampling",
+                settings.pressure_oversampling.to_string(),
+            ),
+            Label::new(
+                "humidity_oversampling",
+                settings.humidity_oversampling.to_string(),
+            ),
+            Label::new("filter", settings.filter.to_string()),
+            Label::new("mode", settings.mode.to_string()),
+            Label::new("version", env!("CARGO_PKG_VERSION")),
+        ]);
+        labels
+    }
+
+    /// Whether humidity and everything derived from it is exported, which is
+    /// the case unless the sensor is known to lack a humidity sensor
+    fn has_humidity(&self) -> bool {
+        let info = self.info.lock().expect("sensor info poisoned");
+        info.as_ref().is_none_or(|info| info.variant.has_humidity())
+    }
+
+    fn record(&self, reading: Reading) {
+        let has_humidity = self.has_humidity();
+        if has_humidity {
+            self.humidity_series
+                .call_once(|| self.register_rejections("humidity"));
+        }
+
+        if let (true, Some(raw)) = (self.raw_adc, reading.raw) {
+            for (channel, value) in [
+                ("temperature", raw.temperature),
+                ("pressure", raw.pressure),
+                ("humidity", raw.humidity.map(u32::from)),
+            ] {
+                if channel == "humidity" && !has_humidity {
+                    continue;
+                }
+                metrics::gauge!(
+                    "bme280_adc_value",
+                    value.map_or(f64::NAN, f64::from),
+                    self.labels_with("channel", channel)
+                );
+            }
+        }
+
+        let (reading, rejections) = self
+            .processor
+            .lock()
+            .expect("processor poisoned")
+            .process(reading, Instant::now());
+        for (channel, rejection) in rejections {
+            metrics::increment_counter!(
+                "bme280_rejected_samples_total",
+                self.rejection_labels(channel, rejection)
+            );
+        }
+
+        let now = unix_time();
+        let temperature = self.channel("temperature", reading.temperature, now);
+        let pressure = self.channel("pressure", reading.pressure, now);
+        let humidity = self.channel("humidity", reading.humidity, now);
+
+        // a calibrated sensor gets two series, each labelled with its reading
+        let (temperature, pressure, humidity, labels) = match &self.calibration {
+            Some(calibration) => {
+                let labels = || self.labels_with("reading", "raw");
+                metrics::gauge!("bme280_temperature_celsius", temperature, labels());
+                metrics::gauge!("bme280_pressure_pascals", pressure, labels());
+                if has_humidity {
+                    metrics::gauge!("bme280_relative_humidity_ratio", humidity / 100.0, labels());
+                }
+
+                let (temperature, pressure, humidity) =
+                    calibration.apply(temperature, pressure, humidity);
+                let labels = self.labels_with("reading", "corrected");
+                (temperature, pressure, humidity, labels)
+            }
+            None => (temperature, pressure, humidity, self.labels.clone()),
+        };
+
+        metrics::gauge!("bme280_temperature_celsius", temperature, labels.iter());
+        metrics::gauge!("bme280_pressure_pascals", pressure, labels.iter());
+        if has_humidity {
+            metrics::gauge!(
+                "bme280_relative_humidity_ratio",
+                humidity / 100.0,
+                labels.iter()
+            );
+        }
+
+        if self.legacy_metric_names {
+            metrics::gauge!("temperature", temperature, self.labels.iter());
+            metrics::gauge!("pressure", pressure / 100.0, self.labels.iter());
+            if has_humidity {
+                metrics::gauge!("humidity", humidity, self.labels.iter());
+            }
+        }
+
+        if has_humidity {
+            self.record_psychrometrics(temperature, pressure, humidity);
+            self.record_comfort(temperature, humidity);
+        }
+        self.record_greenhouse(temperature, has_humidity.then_some(humidity), now);
+        self.record_degree_days(temperature, now);
+
+        let sea_level_pressure = self
+            .station
+            .elevation
+            .map(|elevation| atmosphere::sea_level_pressure(pressure, temperature, elevation));
+        if let Some(sea_level_pressure) = sea_level_pressure {
+            metrics::gauge!(
+                "bme280_sea_level_pressure_pascals",
+                sea_level_pressure,
+                self.labels.iter()
+            );
+        }
+        if let Some(qnh) = self.station.qnh {
+            metrics::gauge!(
+                "bme280_altitude_meters",
+                atmosphere::altitude(pressure, temperature, qnh * 100.0),
+                self.labels.iter()
+            );
+        }
+
+        self.record_forecast(pressure, sea_level_pressure, Instant::now());
+    }
+
+    /// The forecast needs the sea-level pressure, so it is only made with a
+    /// station elevation
+    fn record_forecast(&self, pressure: f64, sea_level_pressure: Option<f64>, now: Instant) {
+        let mut history = self
+            .pressure_history
+            .lock()
+            .expect("pressure history poisoned");
+        history.record(now, pressure);
+
+        for (window, duration) in forecast::WINDOWS {
+            let rate = history.tendency(duration).filter(|_| !pressure.is_nan());
+            metrics::gauge!(
+                "bme280_pressure_tendency_pascals_per_second",
+                rate.unwrap_or(f64::NAN),
+                self.labels_with("window", window)
+            );
+            metrics::gauge!(
+                "bme280_pressure_trend",
+                rate.map_or(f64::NAN, |rate| Trend::from_rate(rate).as_f64()),
+                self.labels_with("window", window)
+            );
+        }
+
+        let trend = history.trend().filter(|_| !pressure.is_nan());
+        let forecast = sea_level_pressure
+            .zip(trend)
+            .and_then(|(sea_level_pressure, trend)| forecast::zambretti(sea_level_pressure, trend));
+
+        // a forecast that can no longer be made is set to 0 rather than
+        // left standing
+        let mut current = self.forecast.lock().expect("forecast poisoned");
+        if *current != forecast {
+            if let Some(previous) = current.take() {
+                metrics::gauge!("bme280_forecast_info", 0.0, self.forecast_labels(previous));
+            }
+            if let Some(forecast) = forecast {
+                metrics::gauge!("bme280_forecast_info", 1.0, self.forecast_labels(forecast));
+                *current = Some(forecast);
+            }
+        }
+    }
+
+    fn forecast_labels(&self, forecast: Forecast) -> Vec<Label> {
+        let mut labels = self.labels_with("zambretti", forecast.letter.to_string());
+        labels.push(Label::new("forecast", forecast.text));
+        labels
+    }
+
+    fn record_comfort(&self, temperature: f64, humidity: f64) {
+        metrics::gauge!(
+            "bme280_heat_index_celsius",
+            comfort::heat_index(temperature, humidity),
+            self.labels.iter()
+        );
+        metrics::gauge!(
+            "bme280_humidex",
+            comfort::humidex(temperature, humidity),
+            self.labels.iter()
+        );
+        metrics::gauge!(
+            "bme280_apparent_temperature_celsius",
+            comfort::apparent_temperature(temperature, humidity),
+            self.labels.iter()
+        );
+
+        let Some(thresholds) = self.comfort else {
+            return;
+        };
+        let zone = Zone::classify(humidity, thresholds.min_humidity, thresholds.max_humidity);
+        for candidate in Zone::ALL {
+            let value = zone.map_or(f64::NAN, |zone| f64::from(u8::from(zone == candidate)));
+            metrics::gauge!(
+                "bme280_comfort_zone",
+                value,
+                self.labels_with("zone", candidate.as_str())
+            );
+        }
+    }
+
+    fn record_greenhouse(&self, temperature: f64, humidity: Option<f64>, now: f64) {
+        if let (Some(offset), Some(humidity)) = (self.greenhouse.leaf_temperature_offset, humidity)
+        {
+            metrics::gauge!(
+                "bme280_leaf_vapour_pressure_deficit_pascals",
+                psychrometrics::leaf_vapour_pressure_deficit(
+                    temperature,
+                    humidity,
+                    temperature + offset
+                ),
+                self.labels.iter()
+            );
+        }
+
+        let Some(growing_degree_days) = self.greenhouse.growing_degree_days else {
+            return;
+        };
+        let mut state = self.state.lock().expect("sensor state poisoned");
+        let season = state.growing_season.get_or_insert_with(Default::default);
+        season.add(
+            now,
+            temperature,
+            growing_degree_days.base_temperature,
+            growing_degree_days.season_start,
+        );
+        metrics::gauge!(
+            "bme280_growing_degree_days",
+            season.accumulator.degree_days(),
+            self.labels.iter()
+        );
+    }
+
+    fn record_degree_days(&self, temperature: f64, now: f64) {
+        let Some(bases) = self.degree_days else {
+            return;
+        };
+        let mut state = self.state.lock().expect("sensor state poisoned");
+
+        state
+            .heating
+            .add(now, bases.heating_base_temperature - temperature);
+        state
+            .cooling
+            .add(now, temperature - bases.cooling_base_temperature);
+        metrics::gauge!(
+            "bme280_heating_degree_days",
+            state.heating.degree_days(),
+            self.labels.iter()
+        );
+        metrics::gauge!(
+            "bme280_cooling_degree_days",
+            state.cooling.degree_days(),
+            self.labels.iter()
+        );
+    }
+
+    fn record_psychrometrics(&self, temperature: f64, pressure: f64, humidity: f64) {
+        let labels = || self.labels.iter();
+
+        metrics::gauge!(
+            "bme280_vapour_pressure_pascals",
+            psychrometrics::vapour_pressure(temperature, humidity),
+            labels()
+        );
+        metrics::gauge!(
+            "bme280_vapour_pressure_deficit_pascals",
+            psychrometrics::vapour_pressure_deficit(temperature, humidity),
+            labels()
+        );
+        metrics::gauge!(
+            "bme280_dew_point_celsius",
+            psychrometrics::dew_point(temperature, humidity),
+            labels()
+        );
+        metrics::gauge!(
+            "bme280_frost_point_celsius",
+            psychrometrics::frost_point(temperature, humidity),
+            labels()
+        );
+        metrics::gauge!(
+            "bme280_absolute_humidity_grams_per_cubic_meter",
+            psychrometrics::absolute_humidity(temperature, humidity),
+            labels()
+        );
+        metrics::gauge!(
+            "bme280_mixing_ratio",
+            psychrometrics::mixing_ratio(temperature, humidity, pressure),
+            labels()
+        );
+        metrics::gauge!(
+            "bme280_wet_bulb_temperature_celsius",
+            psychrometrics::wet_bulb_temperature(temperature, humidity),
+            labels()
+        );
+        metrics::gauge!(
+            "bme280_enthalpy_joules_per_kilogram",
+            psychrometrics::enthalpy(temperature, humidity, pressure),
+            labels()
+        );
+    }
+
+    /// Value of a channel to export, NaN when the sensor did not measure it so
+    /// an earlier value is not served as if it were current
+    fn channel(&self, channel: &'static str, value: Option<f32>, now: f64) -> f64 {
+        match value {
+            Some(value) => {
+                metrics::gauge!(
+                    "bme280_channel_last_updated_timestamp_seconds",
+                    now,
+                    self.labels_with("channel", channel)
+                );
+                f64::from(value)
+            }
+            None => f64::NAN,
+        }
+    }
+}
+
+pub fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{datasheet_bme280, fake_sensor, prometheus, spec};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn forecast_needs_elevation_and_clears_when_unknown() {
+        let mut config = Config::default();
+        config.station.elevation = Some(0.0);
+        let device = datasheet_bme280();
+        let sensor = MonitoredSensor::new(
+            &spec("forecaster", None),
+            fake_sensor(&device, 0x77),
+            &config,
+        );
+        let inland = MonitoredSensor::new(
+            &spec("inland", None),
+            fake_sensor(&device, 0x77),
+            &Config::default(),
+        );
+        let forecasts = |sensor: &str| -> Vec<(String, f64)> {
+            let prefix = format!(r#"bme280_forecast_info{{sensor="{sensor}","#);
+            prometheus()
+                .render()
+                .lines()
+                .filter(|line| line.starts_with(&prefix))
+                .map(|line| {
+                    let (series, value) = line.rsplit_once(' ').expect("sample has a value");
+                    (series.to_owned(), value.parse().expect("value is a number"))
+                })
+                .collect()
+        };
+
+        // rising by 5 hPa in 3 hours to 1015 hPa: Z = 185 - 162.4 = 23
+        let start = Instant::now();
+        let three_hours = Duration::from_secs(3 * 3600);
+        for monitored in [&sensor, &inland] {
+            monitored.record_forecast(101_000.0, Some(101_000.0), start);
+        }
+        sensor.record_forecast(101_500.0, Some(101_500.0), start + three_hours);
+        inland.record_forecast(101_500.0, None, start + three_hours);
+
+        let current = forecasts("forecaster");
+        assert_eq!(current.len(), 1, "{current:?}");
+        assert!(current[0].0.contains(r#"zambretti="F""#), "{current:?}");
+        assert_eq!(current[0].1, 1.0);
+        assert!(forecasts("inland").is_empty());
+
+        // the pressure channel drops out, the forecast is no longer current
+        sensor.record_forecast(
+            f64::NAN,
+            Some(f64::NAN),
+            start + three_hours + Duration::from_secs(1),
+        );
+        assert_eq!(forecasts("forecaster"), vec![(current[0].0.clone(), 0.0)]);
+    }
+
+    #[tokio::test]
+    async fn forecast_survives_a_rejected_temperature() {
+        let mut config = Config::default();
+        config.station.elevation = Some(100.0);
+        let sensor = MonitoredSensor::new(
+            &spec("rejected", None),
+            fake_sensor(&datasheet_bme280(), 0x77),
+            &config,
+        );
+        let start = Instant::now();
+        let three_hours = Duration::from_secs(3 * 3600);
+        sensor.record_forecast(101_000.0, Some(101_000.0), start);
+
+        // the range check turns the temperature into NaN, the pressure stays
+        let sea_level_pressure = atmosphere::sea_level_pressure(101_500.0, f64::NAN, 100.0);
+        sensor.record_forecast(101_500.0, Some(sea_level_pressure), start + three_hours);
+        assert_eq!(*sensor.forecast.lock().expect("forecast poisoned"), None);
+
+        // and the next good sample still makes a forecast
+        let later = start + three_hours + Duration::from_secs(60);
+        sensor.record_forecast(101_500.0, Some(101_500.0), later);
+        assert!(sensor.forecast.lock().expect("forecast poisoned").is_some());
+    }
+}
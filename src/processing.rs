@@ -0,0 +1,412 @@
+//! Post-processing of readings before they are exported: range checks, a
+//! median filter, a rate of change clamp and smoothing
+
+use crate::sensor::Reading;
+use anyhow::bail;
+use serde::Deserialize;
+use std::{collections::VecDeque, ops::RangeInclusive, time::Instant};
+
+/// Operating ranges from datasheet section 1, in °C, Pa and %
+const TEMPERATURE_RANGE: RangeInclusive<f64> = -40.0..=85.0;
+const PRESSURE_RANGE: RangeInclusive<f64> = 30_000.0..=110_000.0;
+const HUMIDITY_RANGE: RangeInclusive<f64> = 0.0..=100.0;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessingConfig {
+    /// Reject values outside the operating range of the sensor
+    pub range_check: bool,
+    /// Export the median of this many recent values, 1 disables the filter
+    pub median_window: usize,
+    pub temperature: ChannelConfig,
+    pub pressure: ChannelConfig,
+    pub humidity: ChannelConfig,
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        Self {
+            range_check: true,
+            median_window: 1,
+            temperature: ChannelConfig::default(),
+            pressure: ChannelConfig::default(),
+            humidity: ChannelConfig::default(),
+        }
+    }
+}
+
+impl ProcessingConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.median_window == 0 {
+            bail!("median window must hold at least one value");
+        }
+
+        for (channel, config) in [
+            ("temperature", self.temperature),
+            ("pressure", self.pressure),
+            ("humidity", self.humidity),
+        ] {
+            if let Some(max_rate) = config.max_rate {
+                if !(max_rate.is_finite() && max_rate > 0.0) {
+                    bail!("{channel} max rate must be a positive number, got {max_rate}");
+                }
+            }
+
+            match config.smoothing {
+                Some(Smoothing::Exponential { alpha }) if !(alpha > 0.0 && alpha <= 1.0) => {
+                    bail!("{channel} smoothing alpha must lie within 0..=1, got {alpha}");
+                }
+                Some(Smoothing::Kalman {
+                    process_noise,
+                    measurement_noise,
+                }) if !(process_noise.is_finite()
+                    && process_noise > 0.0
+                    && measurement_noise.is_finite()
+                    && measurement_noise > 0.0) =>
+                {
+                    bail!("{channel} kalman noise variances must be positive numbers");
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelConfig {
+    /// Largest change per second, in the unit of the channel, larger changes
+    /// are clamped
+    pub max_rate: Option<f64>,
+    pub smoothing: Option<Smoothing>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(tag = "method", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Smoothing {
+    /// Exponential moving average, weighing each new value by alpha
+    Exponential { alpha: f64 },
+    /// One-dimensional Kalman filter for a slowly drifting value, with the
+    /// variances per sample in the squared unit of the channel
+    Kalman {
+        process_noise: f64,
+        measurement_noise: f64,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    OutOfRange,
+    RateLimited,
+}
+
+impl Rejection {
+    pub const ALL: [Self; 2] = [Self::OutOfRange, Self::RateLimited];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::OutOfRange => "out_of_range",
+            Self::RateLimited => "rate_limited",
+        }
+    }
+}
+
+pub const CHANNELS: [&str; 3] = ["temperature", "pressure", "humidity"];
+
+#[derive(Debug)]
+pub struct Processor {
+    temperature: ChannelFilter,
+    pressure: ChannelFilter,
+    humidity: ChannelFilter,
+}
+
+impl Processor {
+    pub fn new(config: &ProcessingConfig) -> Self {
+        let filter = |channel: ChannelConfig, range: RangeInclusive<f64>| ChannelFilter {
+            config: channel,
+            range: config.range_check.then_some(range),
+            median_window: config.median_window,
+            window: VecDeque::with_capacity(config.median_window),
+            last: None,
+            smoothed: None,
+        };
+
+        Self {
+            temperature: filter(config.temperature, TEMPERATURE_RANGE),
+            pressure: filter(config.pressure, PRESSURE_RANGE),
+            humidity: filter(config.humidity, HUMIDITY_RANGE),
+        }
+    }
+
+    /// Processed reading and the channels whose values were rejected or
+    /// clamped, values out of range become `None`
+    pub fn process(
+        &mut self,
+        reading: Reading,
+        at: Instant,
+    ) -> (Reading, Vec<(&'static str, Rejection)>) {
+        let mut rejections = Vec::new();
+        let mut channel = |name, filter: &mut ChannelFilter, value: Option<f32>| {
+            let (value, rejection) = filter.process(value?, at);
+            if let Some(rejection) = rejection {
+                rejections.push((name, rejection));
+            }
+            value
+        };
+
+        let processed = Reading {
+            temperature: channel("temperature", &mut self.temperature, reading.temperature),
+            pressure: channel("pressure", &mut self.pressure, reading.pressure),
+            humidity: channel("humidity", &mut self.humidity, reading.humidity),
+            raw: reading.raw,
+        };
+
+        (processed, rejections)
+    }
+}
+
+#[derive(Debug)]
+struct ChannelFilter {
+    config: ChannelConfig,
+    range: Option<RangeInclusive<f64>>,
+    median_window: usize,
+    /// Recent values in range, oldest first
+    window: VecDeque<f64>,
+    /// Last value after the rate clamp
+    last: Option<(Instant, f64)>,
+    /// Estimate and its variance, the variance is only used by the Kalman
+    /// filter
+    smoothed: Option<(f64, f64)>,
+}
+
+impl ChannelFilter {
+    fn process(&mut self, value: f32, at: Instant) -> (Option<f32>, Option<Rejection>) {
+        let raw = f64::from(value);
+        if self
+            .range
+            .as_ref()
+            .is_some_and(|range| !range.contains(&raw))
+        {
+            return (None, Some(Rejection::OutOfRange));
+        }
+
+        let mut rejection = None;
+        let mut value = self.median(raw);
+
+        if let (Some(max_rate), Some((then, last))) = (self.config.max_rate, self.last) {
+            let max_change = max_rate * at.saturating_duration_since(then).as_secs_f64();
+            let clamped = value.clamp(last - max_change, last + max_change);
+            if clamped != value {
+                rejection = Some(Rejection::RateLimited);
+                value = clamped;
+            }
+        }
+        self.last = Some((at, value));
+
+        let value = match self.config.smoothing {
+            None => value,
+            Some(Smoothing::Exponential { alpha }) => {
+                let estimate = self
+                    .smoothed
+                    .map_or(value, |(estimate, _)| estimate + alpha * (value - estimate));
+                self.smoothed = Some((estimate, 0.0));
+                estimate
+            }
+            Some(Smoothing::Kalman {
+                process_noise,
+                measurement_noise,
+            }) => {
+                let (estimate, variance) = match self.smoothed {
+                    None => (value, measurement_noise),
+                    Some((estimate, variance)) => {
+                        let variance = variance + process_noise;
+                        let gain = variance / (variance + measurement_noise);
+                        (
+                            estimate + gain * (value - estimate),
+                            (1.0 - gain) * variance,
+                        )
+                    }
+                };
+                self.smoothed = Some((estimate, variance));
+                estimate
+            }
+        };
+
+        // f32 to f64 and back is lossless, so unfiltered values pass unchanged
+        (Some(value as f32), rejection)
+    }
+
+    /// Upper median of the recent values, the value itself for a window of 1
+    fn median(&mut self, value: f64) -> f64 {
+        if self.median_window == 1 {
+            return value;
+        }
+
+        if self.window.len() == self.median_window {
+            self.window.pop_front();
+        }
+        self.window.push_back(value);
+
+        let mut sorted: Vec<f64> = self.window.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        sorted[sorted.len() / 2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn humidity(humidity: f32) -> Reading {
+        Reading {
+            temperature: Some(21.0),
+            pressure: Some(100_000.0),
+            humidity: Some(humidity),
+            raw: None,
+        }
+    }
+
+    fn run(config: &ProcessingConfig, values: &[f32]) -> Vec<Option<f32>> {
+        let mut processor = Processor::new(config);
+        let start = Instant::now();
+
+        (0..)
+            .zip(values)
+            .map(|(second, &value)| {
+                let at = start + Duration::from_secs(second);
+                processor.process(humidity(value), at).0.humidity
+            })
+            .collect()
+    }
+
+    #[test]
+    fn passes_readings_through_by_default() {
+        let mut processor = Processor::new(&ProcessingConfig::default());
+        let reading = Reading {
+            temperature: Some(25.08),
+            pressure: Some(100_653.27),
+            humidity: None,
+            raw: None,
+        };
+
+        assert_eq!(
+            processor.process(reading, Instant::now()),
+            (reading, vec![])
+        );
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        let mut processor = Processor::new(&ProcessingConfig::default());
+        let reading = Reading {
+            temperature: Some(-142.0),
+            pressure: Some(100_000.0),
+            humidity: Some(45.0),
+            raw: None,
+        };
+
+        let (processed, rejections) = processor.process(reading, Instant::now());
+        assert_eq!(processed.temperature, None);
+        assert_eq!(processed.humidity, Some(45.0));
+        assert_eq!(rejections, vec![("temperature", Rejection::OutOfRange)]);
+    }
+
+    #[test]
+    fn median_removes_single_spikes() {
+        let config = ProcessingConfig {
+            median_window: 3,
+            ..ProcessingConfig::default()
+        };
+
+        let values = run(&config, &[45.0, 46.0, 100.0, 46.0, 47.0]);
+        assert_eq!(
+            values,
+            [Some(45.0), Some(46.0), Some(46.0), Some(46.0), Some(47.0)]
+        );
+    }
+
+    #[test]
+    fn clamps_rate_of_change() {
+        let mut config = ProcessingConfig::default();
+        config.humidity.max_rate = Some(2.0);
+        let mut processor = Processor::new(&config);
+        let start = Instant::now();
+
+        processor.process(humidity(45.0), start);
+        let (processed, rejections) =
+            processor.process(humidity(100.0), start + Duration::from_secs(5));
+
+        assert_eq!(processed.humidity, Some(55.0));
+        assert_eq!(rejections, vec![("humidity", Rejection::RateLimited)]);
+    }
+
+    #[test]
+    fn smooths_exponentially() {
+        let mut config = ProcessingConfig::default();
+        config.humidity.smoothing = Some(Smoothing::Exponential { alpha: 0.5 });
+
+        let values = run(&config, &[40.0, 50.0, 50.0]);
+        assert_eq!(values, [Some(40.0), Some(45.0), Some(47.5)]);
+    }
+
+    #[test]
+    fn kalman_filter_converges() {
+        let mut config = ProcessingConfig::default();
+        config.humidity.smoothing = Some(Smoothing::Kalman {
+            process_noise: 0.01,
+            measurement_noise: 1.0,
+        });
+
+        let steps: Vec<f32> = std::iter::once(40.0).chain([50.0; 50]).collect();
+        let values = run(&config, &steps);
+        let first_step = values[1].expect("smoothed value");
+        let last = values[50].expect("smoothed value");
+        assert!(
+            40.0 < first_step && first_step < 46.0,
+            "first step {first_step}"
+        );
+        assert!((last - 50.0).abs() < 0.5, "last {last}");
+    }
+
+    #[test]
+    fn parses_config() {
+        let config: ProcessingConfig = toml::from_str(
+            r#"
+            median_window = 5
+
+            [humidity]
+            max_rate = 2
+            smoothing = { method = "kalman", process_noise = 0.01, measurement_noise = 1 }
+
+            [pressure]
+            smoothing = { method = "exponential", alpha = 0.3 }
+            "#,
+        )
+        .expect("failed to parse processing config");
+
+        assert!(config.range_check);
+        assert_eq!(config.median_window, 5);
+        assert_eq!(config.humidity.max_rate, Some(2.0));
+        assert_eq!(
+            config.pressure.smoothing,
+            Some(Smoothing::Exponential { alpha: 0.3 })
+        );
+        config.validate().expect("config should be valid");
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let mut config = ProcessingConfig {
+            median_window: 0,
+            ..ProcessingConfig::default()
+        };
+        assert!(config.validate().is_err());
+
+        config.median_window = 1;
+        config.temperature.smoothing = Some(Smoothing::Exponential { alpha: 1.5 });
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Moist air properties derived from temperature (°C), relative humidity (%)
+//! and pressure (Pa)
+//!
+//! Inputs that are NaN, for example a channel the sensor skipped, give NaN.
+
+/// Magnus coefficients over water from Alduchov and Eskridge (1996)
+const WATER: Magnus = Magnus {
+    a: 17.625,
+    b: 243.04,
+    c: 610.94,
+};
+
+/// Magnus coefficients over ice from Alduchov and Eskridge (1996)
+const ICE: Magnus = Magnus {
+    a: 22.587,
+    b: 273.86,
+    c: 611.21,
+};
+
+/// Specific gas constant of water vapour in J/(kg·K)
+const WATER_VAPOUR_GAS_CONSTANT: f64 = 461.5;
+/// Ratio of the molar masses of water vapour and dry air
+const MOLAR_MASS_RATIO: f64 = 0.622;
+const ZERO_CELSIUS: f64 = 273.15;
+
+struct Magnus {
+    a: f64,
+    b: f64,
+    c: f64,
+}
+
+impl Magnus {
+    /// Saturation vapour pressure in Pa
+    fn saturation_pressure(&self, temperature: f64) -> f64 {
+        self.c * (self.a * temperature / (self.b + temperature)).exp()
+    }
+
+    /// Temperature in °C at which the vapour pressure saturates
+    fn saturation_temperature(&self, vapour_pressure: f64) -> f64 {
+        if vapour_pressure <= 0.0 {
+            return f64::NAN;
+        }
+
+        let gamma = (vapour_pressure / self.c).ln();
+        self.b * gamma / (self.a - gamma)
+    }
+}
+
+/// Saturation vapour pressure over water in Pa
+pub fn saturation_vapour_pressure(temperature: f64) -> f64 {
+    WATER.saturation_pressure(temperature)
+}
+
+/// Partial pressure of water vapour in Pa
+pub fn vapour_pressure(temperature: f64, humidity: f64) -> f64 {
+    humidity / 100.0 * saturation_vapour_pressure(temperature)
+}
+
+/// How much more water vapour the air could hold in Pa, the vapour pressure
+/// deficit (VPD)
+pub fn vapour_pressure_deficit(temperature: f64, humidity: f64) -> f64 {
+    saturation_vapour_pressure(temperature) - vapour_pressure(temperature, humidity)
+}
+
+/// Vapour pressure deficit in Pa between the saturated air inside a leaf at
+/// the leaf temperature in °C and the surrounding air
+pub fn leaf_vapour_pressure_deficit(temperature: f64, humidity: f64, leaf_temperature: f64) -> f64 {
+    saturation_vapour_pressure(leaf_temperature) - vapour_pressure(temperature, humidity)
+}
+
+/// Dew point in °C, NaN for completely dry air
+pub fn dew_point(temperature: f64, humidity: f64) -> f64 {
+    WATER.saturation_temperature(vapour_pressure(temperature, humidity))
+}
+
+/// Frost point in °C, the temperature at which the air saturates over ice
+pub fn frost_point(temperature: f64, humidity: f64) -> f64 {
+    ICE.saturation_temperature(vapour_pressure(temperature, humidity))
+}
+
+/// Absolute humidity in g/m³
+pub fn absolute_humidity(temperature: f64, humidity: f64) -> f64 {
+    let kelvin = temperature + ZERO_CELSIUS;
+    vapour_pressure(temperature, humidity) / (WATER_VAPOUR_GAS_CONSTANT * kelvin) * 1000.0
+}
+
+/// Mass of water vapour per mass of dry air in kg/kg
+pub fn mixing_ratio(temperature: f64, humidity: f64, pressure: f64) -> f64 {
+    let vapour_pressure = vapour_pressure(temperature, humidity);
+    MOLAR_MASS_RATIO * vapour_pressure / (pressure - vapour_pressure)
+}
+
+/// Wet-bulb temperature in °C after Stull (2011)
+///
+/// The fit is accurate to within 1 °C for 5 to 99 % humidity and -20 to
+/// 50 °C at sea-level pressure.
+pub fn wet_bulb_temperature(temperature: f64, humidity: f64) -> f64 {
+    temperature * (0.151_977 * (humidity + 8.313_659).sqrt()).atan()
+        + (temperature + humidity).atan()
+        - (humidity - 1.676_331).atan()
+        + 0.003_918_38 * humidity.powf(1.5) * (0.023_101 * humidity).atan()
+        - 4.686_035
+}
+
+/// Specific enthalpy of moist air in J per kg of dry air, relative to dry air
+/// and liquid water at 0 °C
+pub fn enthalpy(temperature: f64, humidity: f64, pressure: f64) -> f64 {
+    let mixing_ratio = mixing_ratio(temperature, humidity, pressure);
+    1006.0 * temperature + mixing_ratio * (2_501_000.0 + 1860.0 * temperature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {expected} ± {tolerance}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn saturation_vapour_pressure_matches_steam_tables() {
+        // IAPWS values over water and ice
+        assert_close(saturation_vapour_pressure(0.0), 611.2, 1.0);
+        assert_close(saturation_vapour_pressure(20.0), 2339.3, 12.0);
+        assert_close(saturation_vapour_pressure(40.0), 7384.9, 37.0);
+        assert_close(ICE.saturation_pressure(-20.0), 103.3, 0.5);
+    }
+
+    #[test]
+    fn dew_point_matches_reference_table() {
+        assert_close(dew_point(25.0, 60.0), 16.7, 0.1);
+        assert_close(dew_point(20.0, 50.0), 9.3, 0.1);
+        assert_close(dew_point(30.0, 80.0), 26.2, 0.1);
+        assert_close(dew_point(21.0, 100.0), 21.0, 1e-9);
+        assert!(dew_point(21.0, 0.0).is_nan());
+    }
+
+    #[test]
+    fn frost_point_lies_between_dew_point_and_temperature() {
+        let frost_point = frost_point(-10.0, 80.0);
+
+        assert!(dew_point(-10.0, 80.0) < frost_point && frost_point < -10.0);
+        // air saturated over ice at -10 °C is at 91 % relative to water
+        let humidity = 100.0 * ICE.saturation_pressure(-10.0) / saturation_vapour_pressure(-10.0);
+        assert_close(humidity, 90.7, 0.3);
+        assert_close(super::frost_point(-10.0, humidity), -10.0, 1e-9);
+    }
+
+    #[test]
+    fn absolute_humidity_matches_reference_table() {
+        // saturated air holds 17.3 g/m³ at 20 °C and 30.4 g/m³ at 30 °C
+        assert_close(absolute_humidity(20.0, 100.0), 17.3, 0.1);
+        assert_close(absolute_humidity(30.0, 100.0), 30.4, 0.2);
+    }
+
+    #[test]
+    fn mixing_ratio_and_enthalpy_match_psychrometric_chart() {
+        // ASHRAE chart at sea level: 25 °C and 50 % is 9.9 g/kg and 50.3 kJ/kg
+        assert_close(mixing_ratio(25.0, 50.0, 101_325.0), 0.0099, 0.0001);
+        assert_close(enthalpy(25.0, 50.0, 101_325.0), 50_300.0, 300.0);
+    }
+
+    #[test]
+    fn vapour_pressure_deficit_matches_reference_table() {
+        // 25 °C at 60 % is 1.27 kPa, a leaf 2 °C cooler sees 0.91 kPa
+        assert_close(vapour_pressure_deficit(25.0, 60.0), 1270.0, 10.0);
+        assert_close(leaf_vapour_pressure_deficit(25.0, 60.0, 23.0), 910.0, 10.0);
+        assert_eq!(
+            leaf_vapour_pressure_deficit(25.0, 60.0, 25.0),
+            vapour_pressure_deficit(25.0, 60.0)
+        );
+        assert_eq!(vapour_pressure_deficit(25.0, 100.0), 0.0);
+    }
+
+    #[test]
+    fn wet_bulb_matches_stull() {
+        // worked example from Stull (2011)
+        assert_close(wet_bulb_temperature(20.0, 50.0), 13.7, 0.05);
+        assert_close(wet_bulb_temperature(30.0, 90.0), 28.6, 0.3);
+    }
+
+    #[test]
+    fn missing_channels_propagate_as_nan() {
+        assert!(dew_point(f64::NAN, 50.0).is_nan());
+        assert!(mixing_ratio(20.0, 50.0, f64::NAN).is_nan());
+        assert!(wet_bulb_temperature(20.0, f64::NAN).is_nan());
+    }
+}
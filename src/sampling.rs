@@ -0,0 +1,632 @@
+use anyhow::bail;
+use bme280_rs::Configuration;
+use clap::{Args, ValueEnum};
+use serde::Deserialize;
+use std::{fmt, str::FromStr, time::Duration};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "Setting")]
+pub enum Oversampling {
+    Skip,
+    X1,
+    X2,
+    X4,
+    X8,
+    X16,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "Setting")]
+pub enum Filter {
+    Off,
+    X2,
+    X4,
+    X8,
+    X16,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "Setting")]
+pub enum Mode {
+    Forced,
+    Normal,
+}
+
+/// Inactive time between measurements in normal mode
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "Setting")]
+pub enum StandbyTime {
+    Millis0_5,
+    Millis10,
+    Millis20,
+    Millis62_5,
+    Millis125,
+    Millis250,
+    Millis500,
+    Millis1000,
+}
+
+/// Recommended modes of operation from datasheet section 3.5
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    Weather,
+    Humidity,
+    IndoorNavigation,
+    Gaming,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SamplingSettings {
+    pub temperature_oversampling: Oversampling,
+    pub pressure_oversampling: Oversampling,
+    pub humidity_oversampling: Oversampling,
+    pub filter: Filter,
+    pub mode: Mode,
+    pub standby_time: Option<StandbyTime>,
+}
+
+#[derive(Args, Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[command(next_help_heading = "Sampling")]
+pub struct SamplingOptions {
+    /// Datasheet recommended settings to start from, individual settings override it
+    #[arg(long)]
+    profile: Option<Profile>,
+
+    /// Temperature oversampling: 1, 2, 4, 8 or 16 [default: 8]
+    #[arg(long, value_name = "OVERSAMPLING")]
+    temperature_oversampling: Option<Oversampling>,
+
+    /// Pressure oversampling: skip, 1, 2, 4, 8 or 16 [default: 8]
+    #[arg(long, value_name = "OVERSAMPLING")]
+    pressure_oversampling: Option<Oversampling>,
+
+    /// Humidity oversampling: skip, 1, 2, 4, 8 or 16 [default: 8]
+    #[arg(long, value_name = "OVERSAMPLING")]
+    humidity_oversampling: Option<Oversampling>,
+
+    /// IIR filter coefficient: off, 2, 4, 8 or 16 [default: 4]
+    #[arg(long, value_name = "COEFFICIENT")]
+    filter: Option<Filter>,
+
+    /// Sensor mode: forced or normal [default: forced]
+    #[arg(long)]
+    mode: Option<Mode>,
+
+    /// Standby time between measurements in normal mode:
+    /// 0.5, 10, 20, 62.5, 125, 250, 500 or 1000 ms [default: 0.5]
+    #[arg(long, value_name = "MILLISECONDS")]
+    standby_time: Option<StandbyTime>,
+}
+
+impl SamplingOptions {
+    /// Combine with options that take precedence, such as command line flags
+    pub fn overridden_by(&self, other: &Self) -> Self {
+        Self {
+            profile: other.profile.or(self.profile),
+            temperature_oversampling: other
+                .temperature_oversampling
+                .or(self.temperature_oversampling),
+            pressure_oversampling: other.pressure_oversampling.or(self.pressure_oversampling),
+            humidity_oversampling: other.humidity_oversampling.or(self.humidity_oversampling),
+            filter: other.filter.or(self.filter),
+            mode: other.mode.or(self.mode),
+            standby_time: other.standby_time.or(self.standby_time),
+        }
+    }
+
+    pub fn settings(&self) -> SamplingSettings {
+        let base = self.profile.map(Profile::settings).unwrap_or_default();
+        let mode = self.mode.unwrap_or(base.mode);
+
+        SamplingSettings {
+            temperature_oversampling: self
+                .temperature_oversampling
+                .unwrap_or(base.temperature_oversampling),
+            pressure_oversampling: self
+                .pressure_oversampling
+                .unwrap_or(base.pressure_oversampling),
+            humidity_oversampling: self
+                .humidity_oversampling
+                .unwrap_or(base.humidity_oversampling),
+            filter: self.filter.unwrap_or(base.filter),
+            mode,
+            // a profile's standby time should not trip validation when the
+            // mode is overridden to forced
+            standby_time: self
+                .standby_time
+                .or(base.standby_time.filter(|_| mode == Mode::Normal)),
+        }
+    }
+}
+
+impl Profile {
+    pub fn settings(self) -> SamplingSettings {
+        match self {
+            Self::Weather => SamplingSettings {
+                temperature_oversampling: Oversampling::X1,
+                pressure_oversampling: Oversampling::X1,
+                humidity_oversampling: Oversampling::X1,
+                filter: Filter::Off,
+                mode: Mode::Forced,
+                standby_time: None,
+            },
+            Self::Humidity => SamplingSettings {
+                temperature_oversampling: Oversampling::X1,
+                pressure_oversampling: Oversampling::Skip,
+                humidity_oversampling: Oversampling::X1,
+                filter: Filter::Off,
+                mode: Mode::Forced,
+                standby_time: None,
+            },
+            Self::IndoorNavigation => SamplingSettings {
+                temperature_oversampling: Oversampling::X2,
+                pressure_oversampling: Oversampling::X16,
+                humidity_oversampling: Oversampling::X1,
+                filter: Filter::X16,
+                mode: Mode::Normal,
+                standby_time: Some(StandbyTime::Millis0_5),
+            },
+            Self::Gaming => SamplingSettings {
+                temperature_oversampling: Oversampling::X1,
+                pressure_oversampling: Oversampling::X4,
+                humidity_oversampling: Oversampling::Skip,
+                filter: Filter::X16,
+                mode: Mode::Normal,
+                standby_time: Some(StandbyTime::Millis0_5),
+            },
+        }
+    }
+}
+
+impl Default for SamplingSettings {
+    fn default() -> Self {
+        Self {
+            temperature_oversampling: Oversampling::X8,
+            pressure_oversampling: Oversampling::X8,
+            humidity_oversampling: Oversampling::X8,
+            filter: Filter::X4,
+            mode: Mode::Forced,
+            standby_time: None,
+        }
+    }
+}
+
+impl SamplingSettings {
+    pub fn configuration(&self) -> Configuration {
+        Configuration::default()
+            .with_temperature_oversampling(self.temperature_oversampling.into())
+            .with_pressure_oversampling(self.pressure_oversampling.into())
+            .with_humidity_oversampling(self.humidity_oversampling.into())
+            .with_filter(self.filter.into())
+            .with_sensor_mode(self.mode.into())
+            .with_standby_time(self.standby_time.unwrap_or(StandbyTime::Millis0_5).into())
+    }
+
+    /// Contents of the config, ctrl_meas and ctrl_hum registers, for sensors
+    /// bme280-rs does not drive
+    pub fn registers(&self) -> (u8, u8, u8) {
+        let standby_time = self.standby_time.unwrap_or(StandbyTime::Millis0_5);
+        let config = standby_time.bits() << 5 | self.filter.bits() << 2;
+        let ctrl_meas = self.temperature_oversampling.bits() << 5
+            | self.pressure_oversampling.bits() << 2
+            | self.mode.bits();
+
+        (config, ctrl_meas, self.humidity_oversampling.bits())
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        // pressure and humidity compensation both depend on the temperature
+        if self.temperature_oversampling == Oversampling::Skip {
+            bail!("temperature oversampling cannot be skip, it is needed to compensate pressure and humidity");
+        }
+
+        if self.mode == Mode::Forced && self.standby_time.is_some() {
+            bail!("standby time only applies in normal mode");
+        }
+
+        Ok(())
+    }
+
+    /// Maximum duration of a single measurement, from datasheet section 9.1
+    pub fn measurement_time(&self) -> Duration {
+        let channel = |oversampling: Oversampling, overhead: f64| match oversampling.samples() {
+            0 => 0.0,
+            samples => 2.3 * f64::from(samples) + overhead,
+        };
+
+        let millis = 1.25
+            + channel(self.temperature_oversampling, 0.0)
+            + channel(self.pressure_oversampling, 0.575)
+            + channel(self.humidity_oversampling, 0.575);
+
+        Duration::from_secs_f64(millis / 1000.0)
+    }
+}
+
+impl Oversampling {
+    fn bits(self) -> u8 {
+        match self {
+            Self::Skip => 0b000,
+            Self::X1 => 0b001,
+            Self::X2 => 0b010,
+            Self::X4 => 0b011,
+            Self::X8 => 0b100,
+            Self::X16 => 0b101,
+        }
+    }
+
+    fn samples(self) -> u8 {
+        match self {
+            Self::Skip => 0,
+            Self::X1 => 1,
+            Self::X2 => 2,
+            Self::X4 => 4,
+            Self::X8 => 8,
+            Self::X16 => 16,
+        }
+    }
+}
+
+impl Filter {
+    fn bits(self) -> u8 {
+        match self {
+            Self::Off => 0b000,
+            Self::X2 => 0b001,
+            Self::X4 => 0b010,
+            Self::X8 => 0b011,
+            Self::X16 => 0b100,
+        }
+    }
+}
+
+impl Mode {
+    fn bits(self) -> u8 {
+        match self {
+            Self::Forced => 0b01,
+            Self::Normal => 0b11,
+        }
+    }
+}
+
+impl StandbyTime {
+    fn bits(self) -> u8 {
+        match self {
+            Self::Millis0_5 => 0b000,
+            Self::Millis62_5 => 0b001,
+            Self::Millis125 => 0b010,
+            Self::Millis250 => 0b011,
+            Self::Millis500 => 0b100,
+            Self::Millis1000 => 0b101,
+            Self::Millis10 => 0b110,
+            Self::Millis20 => 0b111,
+        }
+    }
+}
+
+impl From<Oversampling> for bme280_rs::Oversampling {
+    fn from(oversampling: Oversampling) -> Self {
+        match oversampling {
+            Oversampling::Skip => Self::Skip,
+            Oversampling::X1 => Self::Oversample1,
+            Oversampling::X2 => Self::Oversample2,
+            Oversampling::X4 => Self::Oversample4,
+            Oversampling::X8 => Self::Oversample8,
+            Oversampling::X16 => Self::Oversample16,
+        }
+    }
+}
+
+impl From<Filter> for bme280_rs::Filter {
+    fn from(filter: Filter) -> Self {
+        match filter {
+            Filter::Off => Self::Off,
+            Filter::X2 => Self::Filter2,
+            Filter::X4 => Self::Filter4,
+            Filter::X8 => Self::Filter8,
+            Filter::X16 => Self::Filter16,
+        }
+    }
+}
+
+impl From<Mode> for bme280_rs::SensorMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Forced => Self::Forced,
+            Mode::Normal => Self::Normal,
+        }
+    }
+}
+
+impl From<StandbyTime> for bme280_rs::StandbyTime {
+    fn from(standby_time: StandbyTime) -> Self {
+        match standby_time {
+            StandbyTime::Millis0_5 => Self::Millis0_5,
+            StandbyTime::Millis10 => Self::Millis10,
+            StandbyTime::Millis20 => Self::Millis20,
+            StandbyTime::Millis62_5 => Self::Millis62_5,
+            StandbyTime::Millis125 => Self::Millis125,
+            StandbyTime::Millis250 => Self::Millis250,
+            StandbyTime::Millis500 => Self::Millis500,
+            StandbyTime::Millis1000 => Self::Millis1000,
+        }
+    }
+}
+
+impl FromStr for Oversampling {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "skip" | "0" => Ok(Self::Skip),
+            "1" => Ok(Self::X1),
+            "2" => Ok(Self::X2),
+            "4" => Ok(Self::X4),
+            "8" => Ok(Self::X8),
+            "16" => Ok(Self::X16),
+            _ => Err(format!(
+                "expected oversampling skip, 1, 2, 4, 8 or 16, got {value:?}"
+            )),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "off" | "0" => Ok(Self::Off),
+            "2" => Ok(Self::X2),
+            "4" => Ok(Self::X4),
+            "8" => Ok(Self::X8),
+            "16" => Ok(Self::X16),
+            _ => Err(format!(
+                "expected filter coefficient off, 2, 4, 8 or 16, got {value:?}"
+            )),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "forced" => Ok(Self::Forced),
+            "normal" => Ok(Self::Normal),
+            _ => Err(format!("expected mode forced or normal, got {value:?}")),
+        }
+    }
+}
+
+impl FromStr for StandbyTime {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim_end_matches("ms").trim() {
+            "0.5" => Ok(Self::Millis0_5),
+            "10" => Ok(Self::Millis10),
+            "20" => Ok(Self::Millis20),
+            "62.5" => Ok(Self::Millis62_5),
+            "125" => Ok(Self::Millis125),
+            "250" => Ok(Self::Millis250),
+            "500" => Ok(Self::Millis500),
+            "1000" => Ok(Self::Millis1000),
+            _ => Err(format!(
+                "expected standby time 0.5, 10, 20, 62.5, 125, 250, 500 or 1000 ms, got {value:?}"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Oversampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Skip => write!(f, "skip"),
+            Self::X1 => write!(f, "1"),
+            Self::X2 => write!(f, "2"),
+            Self::X4 => write!(f, "4"),
+            Self::X8 => write!(f, "8"),
+            Self::X16 => write!(f, "16"),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::X2 => write!(f, "2"),
+            Self::X4 => write!(f, "4"),
+            Self::X8 => write!(f, "8"),
+            Self::X16 => write!(f, "16"),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Forced => write!(f, "forced"),
+            Self::Normal => write!(f, "normal"),
+        }
+    }
+}
+
+impl fmt::Display for StandbyTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Millis0_5 => write!(f, "0.5ms"),
+            Self::Millis10 => write!(f, "10ms"),
+            Self::Millis20 => write!(f, "20ms"),
+            Self::Millis62_5 => write!(f, "62.5ms"),
+            Self::Millis125 => write!(f, "125ms"),
+            Self::Millis250 => write!(f, "250ms"),
+            Self::Millis500 => write!(f, "500ms"),
+            Self::Millis1000 => write!(f, "1000ms"),
+        }
+    }
+}
+
+/// Settings can be written both as `filter = 4` and `filter = "4"` in the
+/// configuration file, this funnels either through `FromStr`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Setting {
+    Text(String),
+    Number(f64),
+}
+
+impl Setting {
+    fn parse<T: FromStr>(self) -> Result<T, T::Err> {
+        match self {
+            Self::Text(text) => text.parse(),
+            Self::Number(number) => number.to_string().parse(),
+        }
+    }
+}
+
+impl TryFrom<Setting> for Oversampling {
+    type Error = String;
+
+    fn try_from(setting: Setting) -> Result<Self, Self::Error> {
+        setting.parse()
+    }
+}
+
+impl TryFrom<Setting> for Filter {
+    type Error = String;
+
+    fn try_from(setting: Setting) -> Result<Self, Self::Error> {
+        setting.parse()
+    }
+}
+
+impl TryFrom<Setting> for Mode {
+    type Error = String;
+
+    fn try_from(setting: Setting) -> Result<Self, Self::Error> {
+        setting.parse()
+    }
+}
+
+impl TryFrom<Setting> for StandbyTime {
+    type Error = String;
+
+    fn try_from(setting: Setting) -> Result<Self, Self::Error> {
+        setting.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_matches_previous_hard_coded_configuration() {
+        let settings = SamplingSettings::default();
+
+        settings
+            .validate()
+            .expect("default settings should be valid");
+        assert_eq!(
+            settings.configuration(),
+            Configuration::default()
+                .with_filter(bme280_rs::Filter::Filter4)
+                .with_temperature_oversampling(bme280_rs::Oversampling::Oversample8)
+                .with_pressure_oversampling(bme280_rs::Oversampling::Oversample8)
+                .with_humidity_oversampling(bme280_rs::Oversampling::Oversample8)
+                .with_sensor_mode(bme280_rs::SensorMode::Forced)
+        );
+    }
+
+    #[test]
+    fn rejects_skipping_temperature() {
+        let settings = SamplingSettings {
+            temperature_oversampling: Oversampling::Skip,
+            ..SamplingSettings::default()
+        };
+
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_standby_time_in_forced_mode() {
+        let settings = SamplingSettings {
+            standby_time: Some(StandbyTime::Millis125),
+            ..SamplingSettings::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = SamplingSettings {
+            mode: Mode::Normal,
+            ..settings
+        };
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn measurement_time_follows_datasheet() {
+        let settings = SamplingSettings {
+            temperature_oversampling: Oversampling::X1,
+            pressure_oversampling: Oversampling::X1,
+            humidity_oversampling: Oversampling::X1,
+            ..SamplingSettings::default()
+        };
+        // datasheet table 13, weather monitoring: 9.3 ms maximum
+        assert_eq!(settings.measurement_time().as_micros(), 9_300);
+
+        let settings = SamplingSettings {
+            humidity_oversampling: Oversampling::Skip,
+            ..settings
+        };
+        assert_eq!(settings.measurement_time().as_micros(), 6_425);
+    }
+
+    #[test]
+    fn profile_applies_datasheet_preset() {
+        let options = SamplingOptions {
+            profile: Some(Profile::IndoorNavigation),
+            ..SamplingOptions::default()
+        };
+
+        let settings = options.settings();
+        assert_eq!(settings, Profile::IndoorNavigation.settings());
+        assert_eq!(settings.pressure_oversampling, Oversampling::X16);
+        assert_eq!(settings.filter, Filter::X16);
+        settings.validate().expect("preset should be valid");
+    }
+
+    #[test]
+    fn individual_settings_override_profile() {
+        let file = SamplingOptions {
+            profile: Some(Profile::Weather),
+            filter: Some(Filter::X2),
+            ..SamplingOptions::default()
+        };
+        let cli = SamplingOptions {
+            profile: Some(Profile::Gaming),
+            mode: Some(Mode::Forced),
+            ..SamplingOptions::default()
+        };
+
+        let settings = file.overridden_by(&cli).settings();
+        assert_eq!(settings.pressure_oversampling, Oversampling::X4);
+        assert_eq!(settings.humidity_oversampling, Oversampling::Skip);
+        assert_eq!(settings.filter, Filter::X2);
+        assert_eq!(settings.mode, Mode::Forced);
+        assert_eq!(settings.standby_time, None);
+        settings
+            .validate()
+            .expect("overridden preset should be valid");
+    }
+
+    #[test]
+    fn parses_standby_time() {
+        assert_eq!("62.5".parse(), Ok(StandbyTime::Millis62_5));
+        assert_eq!("1000ms".parse(), Ok(StandbyTime::Millis1000));
+        assert!("100".parse::<StandbyTime>().is_err());
+    }
+}
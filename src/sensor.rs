@@ -0,0 +1,244 @@
+mod bme280;
+mod bmp280;
+mod bus;
+#[cfg(test)]
+pub mod fake;
+mod registers;
+mod simulated;
+pub mod spi;
+
+pub use self::bme280::{probe_address, Address, Bme280Sensor};
+pub use self::bmp280::Bmp280Sensor;
+pub use self::bus::Bus;
+pub use self::registers::{Inspection, RawAdc};
+pub use self::simulated::SimulatedSensor;
+use crate::{calibration::Calibration, sampling::SamplingSettings};
+use anyhow::bail;
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Read, Write, WriteRead},
+};
+use serde::Deserialize;
+use std::str::FromStr;
+use tracing::info;
+
+/// Temperature in °C, pressure in Pa and relative humidity in %
+///
+/// Channels the sensor did not measure are `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Reading {
+    pub temperature: Option<f32>,
+    pub pressure: Option<f32>,
+    pub humidity: Option<f32>,
+    /// Uncompensated ADC values, for sensors that expose them and only when
+    /// asked for
+    pub raw: Option<RawAdc>,
+}
+
+/// Chips that share the BME280 register layout, told apart by their chip ID
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    Bme280,
+    /// Temperature and pressure only, often sold as a BME280
+    Bmp280,
+}
+
+impl Variant {
+    pub fn from_chip_id(chip_id: u8) -> Option<Self> {
+        match chip_id {
+            bme280_rs::CHIP_ID => Some(Self::Bme280),
+            // 0x56 and 0x57 are engineering samples
+            0x56..=0x58 => Some(Self::Bmp280),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Bme280 => "bme280",
+            Self::Bmp280 => "bmp280",
+        }
+    }
+
+    pub fn has_humidity(self) -> bool {
+        self == Self::Bme280
+    }
+}
+
+/// What a connected sensor is and how it was configured
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SensorInfo {
+    pub variant: Variant,
+    pub chip_id: u8,
+    pub address: u8,
+    pub settings: SamplingSettings,
+}
+
+pub trait Sensor: Send {
+    /// Bring the chip into a known state, also used to recover after a reset
+    fn setup(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn take_forced_measurement(&mut self) -> anyhow::Result<()>;
+
+    fn read_sample(&mut self) -> anyhow::Result<Reading>;
+
+    /// Trimming parameters and register contents, `None` for sensors without
+    /// registers
+    fn inspect(&mut self) -> anyhow::Result<Option<Inspection>> {
+        Ok(None)
+    }
+
+    /// `None` for sensors without a chip, or before the setup
+    fn info(&self) -> Option<SensorInfo> {
+        None
+    }
+}
+
+/// Read the chip ID and pick the driver for it, the sensor still needs a setup
+pub fn open<I2C, D, E>(
+    mut i2c: I2C,
+    address: u8,
+    delay: D,
+    settings: &SamplingSettings,
+    raw_adc: bool,
+) -> anyhow::Result<Box<dyn Sensor>>
+where
+    I2C: Read<Error = E> + Write<Error = E> + WriteRead<Error = E> + Send + 'static,
+    D: DelayMs<u32> + Send + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let chip_id = registers::read_chip_id(&mut i2c, address)?;
+    let Some(variant) = Variant::from_chip_id(chip_id) else {
+        bail!("unsupported chip id {chip_id:#04x} at address {address:#04x}");
+    };
+    info!(
+        variant = variant.as_str(),
+        chip_id = format!("{chip_id:#04x}"),
+        "detected sensor"
+    );
+
+    Ok(match variant {
+        Variant::Bme280 => {
+            Box::new(Bme280Sensor::new(i2c, address, delay, settings.clone()).with_raw_adc(raw_adc))
+        }
+        Variant::Bmp280 => {
+            Box::new(Bmp280Sensor::new(i2c, address, delay, settings.clone()).with_raw_adc(raw_adc))
+        }
+    })
+}
+
+/// A sensor from the configuration file, or given on the command line as
+/// `name=<name>,device=<bus>[,address=<address>][,location=<location>]`
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SensorSpec {
+    pub name: String,
+    pub device: Bus,
+    pub address: Option<Address>,
+    pub location: Option<String>,
+    /// Only available in the configuration file
+    pub calibration: Option<Calibration>,
+}
+
+impl SensorSpec {
+    pub fn new(name: &str, device: Bus) -> Self {
+        Self {
+            name: name.to_owned(),
+            device,
+            address: None,
+            location: None,
+            calibration: None,
+        }
+    }
+}
+
+impl FromStr for SensorSpec {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut name = None;
+        let mut device = None;
+        let mut address = None;
+        let mut location = None;
+
+        for field in value.split(',') {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {field:?}"))?;
+
+            match key.trim() {
+                "name" => name = Some(value.to_owned()),
+                "device" => device = Some(value.parse()?),
+                "address" => address = Some(value.parse()?),
+                "location" => location = Some(value.to_owned()),
+                key => return Err(format!("unknown sensor field {key:?}")),
+            }
+        }
+
+        Ok(Self {
+            name: name.ok_or("sensor is missing a name")?,
+            device: device.ok_or("sensor is missing a device")?,
+            address,
+            location,
+            calibration: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn parses_sensor_spec() {
+        let spec: SensorSpec = "name=outside,device=/dev/i2c-1,address=0x76,location=garden"
+            .parse()
+            .expect("failed to parse sensor spec");
+
+        assert_eq!(
+            spec,
+            SensorSpec {
+                name: "outside".to_owned(),
+                device: Bus::I2c(PathBuf::from("/dev/i2c-1")),
+                address: Some(Address::Fixed(0x76)),
+                location: Some("garden".to_owned()),
+                calibration: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_spi_device() {
+        let spec: SensorSpec = "name=hat,device=spi:/dev/spidev0.0"
+            .parse()
+            .expect("failed to parse sensor spec");
+
+        assert_eq!(spec.device, Bus::Spi(PathBuf::from("/dev/spidev0.0")));
+        assert_eq!(spec.device.to_string(), "spi:/dev/spidev0.0");
+        assert_eq!(
+            "i2c:/dev/i2c-1".parse(),
+            Ok(Bus::I2c(PathBuf::from("/dev/i2c-1")))
+        );
+        assert!("spi:".parse::<Bus>().is_err());
+    }
+
+    #[test]
+    fn detects_variant_from_chip_id() {
+        assert_eq!(Variant::from_chip_id(0x60), Some(Variant::Bme280));
+        assert_eq!(Variant::from_chip_id(0x58), Some(Variant::Bmp280));
+        assert_eq!(Variant::from_chip_id(0x61), None);
+        assert!(!Variant::Bmp280.has_humidity());
+    }
+
+    #[test]
+    fn rejects_incomplete_sensor_spec() {
+        assert!("device=/dev/i2c-1".parse::<SensorSpec>().is_err());
+        assert!("name=indoor".parse::<SensorSpec>().is_err());
+        assert!("name=indoor,device=/dev/i2c-1,bus=spi"
+            .parse::<SensorSpec>()
+            .is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use super::{bus::SharedBus, registers, Inspection, Reading, Sensor, SensorInfo, Variant};
+use crate::sampling::SamplingSettings;
+use anyhow::{bail, Context};
+use bme280_rs::Bme280;
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Read, Write, WriteRead},
+};
+use serde::Deserialize;
+use std::{fmt, str::FromStr, time::Duration};
+use tracing::{debug, info};
+
+const ADDRESSES: [u8; 2] = [0x76, 0x77];
+const RESET_POLLS: usize = 10;
+
+/// I²C address of the sensor, which depends on how SDO is wired
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Address {
+    Fixed(u8),
+    Auto,
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "0x76" => Ok(Self::Fixed(0x76)),
+            "0x77" => Ok(Self::Fixed(0x77)),
+            _ => Err(format!("expected 0x76, 0x77 or auto, got {value:?}")),
+        }
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fixed(address) => write!(f, "{address:#04x}"),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// Look for a BME280 or BMP280 chip ID on both addresses the sensor can use
+pub fn probe_address<I2C: WriteRead>(i2c: &mut I2C) -> Option<u8> {
+    ADDRESSES.into_iter().find(|&address| {
+        let found = registers::read_chip_id(i2c, address)
+            .is_ok_and(|chip_id| Variant::from_chip_id(chip_id).is_some());
+
+        debug!(
+            address = format!("{address:#04x}"),
+            found, "probed i2c address"
+        );
+        found
+    })
+}
+
+pub struct Bme280Sensor<I2C, D> {
+    bme280: Bme280<SharedBus<I2C>, D>,
+    /// The driver's bus, to read registers it does not expose
+    bus: SharedBus<I2C>,
+    address: u8,
+    settings: SamplingSettings,
+    /// Keep the ADC values each sample was compensated from
+    raw_adc: bool,
+    /// Read during the setup
+    chip_id: Option<u8>,
+}
+
+impl<I2C, D, E> Bme280Sensor<I2C, D>
+where
+    I2C: Read<Error = E> + Write<Error = E> + WriteRead<Error = E>,
+    D: DelayMs<u32>,
+{
+    pub fn new(i2c: I2C, address: u8, delay: D, settings: SamplingSettings) -> Self {
+        let bus = SharedBus::new(i2c);
+
+        Self {
+            bme280: Bme280::new_with_address(bus.clone(), address, delay),
+            bus,
+            address,
+            settings,
+            raw_adc: false,
+            chip_id: None,
+        }
+    }
+
+    pub fn with_raw_adc(self, raw_adc: bool) -> Self {
+        Self { raw_adc, ..self }
+    }
+}
+
+impl<I2C, D, E> Sensor for Bme280Sensor<I2C, D>
+where
+    I2C: Read<Error = E> + Write<Error = E> + WriteRead<Error = E> + Send,
+    D: DelayMs<u32> + Send,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn setup(&mut self) -> anyhow::Result<()> {
+        info!("initializing bme280 sensor");
+        // a floating spi bus reads all ones, which is no known chip
+        let chip_id = registers::read_chip_id(&mut self.bus, self.address)?;
+        if Variant::from_chip_id(chip_id) != Some(Variant::Bme280) {
+            bail!("expected a bme280, found chip id {chip_id:#04x}");
+        }
+        // bme280-rs waits for the trimming parameters without a limit, so
+        // make sure the chip gets through a reset before handing it over
+        if !registers::reset(&mut self.bus, self.address, RESET_POLLS, |ms| {
+            std::thread::sleep(Duration::from_millis(ms.into()))
+        })? {
+            bail!("bme280 did not finish its reset");
+        }
+        self.bme280.init()?;
+        self.chip_id = Some(chip_id);
+
+        info!("configuring bme280 sensor");
+        self.bme280
+            .set_sampling_configuration(self.settings.configuration())?;
+
+        Ok(())
+    }
+
+    fn take_forced_measurement(&mut self) -> anyhow::Result<()> {
+        // bme280-rs polls the wrong status bit and returns before the
+        // conversion is done, so wait out the datasheet maximum instead
+        if self.bme280.take_forced_measurement()? {
+            std::thread::sleep(self.settings.measurement_time());
+        }
+
+        Ok(())
+    }
+
+    fn read_sample(&mut self) -> anyhow::Result<Reading> {
+        let (temperature, pressure, humidity) = self.bme280.read_sample()?;
+        // in normal mode a second read could already see the next
+        // conversion, so take the burst bme280-rs just compensated
+        let data = self.bus.take_data();
+        let raw = if self.raw_adc {
+            let data = data.context("bme280-rs did not read the data registers")?;
+            Some(registers::parse_raw_adc(&data))
+        } else {
+            None
+        };
+
+        Ok(Reading {
+            temperature,
+            pressure,
+            humidity,
+            raw,
+        })
+    }
+
+    fn inspect(&mut self) -> anyhow::Result<Option<Inspection>> {
+        Ok(Some(registers::inspect(&mut self.bus, self.address, true)?))
+    }
+
+    fn info(&self) -> Option<SensorInfo> {
+        Some(SensorInfo {
+            variant: Variant::Bme280,
+            chip_id: self.chip_id?,
+            address: self.address,
+            settings: self.settings.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::{Filter, Mode, Oversampling, SamplingSettings, StandbyTime};
+    use crate::sensor::fake::{
+        Calibration, FakeBme280, NoopDelay, REGISTER_CONFIG, REGISTER_CTRL_HUM, REGISTER_CTRL_MEAS,
+        REGISTER_STATUS,
+    };
+    use crate::sensor::{RawAdc, SensorInfo};
+
+    const ADDRESS: u8 = 0x77;
+
+    fn sensor(device: &FakeBme280) -> Bme280Sensor<FakeBme280, NoopDelay> {
+        sensor_with_settings(device, SamplingSettings::default())
+    }
+
+    fn sensor_with_settings(
+        device: &FakeBme280,
+        settings: SamplingSettings,
+    ) -> Bme280Sensor<FakeBme280, NoopDelay> {
+        let mut sensor =
+            Bme280Sensor::new(device.clone(), ADDRESS, NoopDelay, settings).with_raw_adc(true);
+        sensor.setup().expect("failed to setup fake bme280");
+        sensor
+    }
+
+    #[test]
+    fn setup_writes_sampling_configuration() {
+        let device = FakeBme280::new(ADDRESS, Calibration::DATASHEET);
+        sensor(&device);
+
+        // osrs_h = x8
+        assert_eq!(device.register(REGISTER_CTRL_HUM), 0b100);
+        // t_sb = 0.5 ms, filter = 4
+        assert_eq!(device.register(REGISTER_CONFIG), 0b010 << 2);
+        // osrs_t = x8, osrs_p = x8, back to sleep after the forced conversion
+        assert_eq!(device.register(REGISTER_CTRL_MEAS), 0b100 << 5 | 0b100 << 2);
+        assert_eq!(device.conversions(), 1);
+    }
+
+    #[test]
+    fn register_values_match_driver() {
+        let device = FakeBme280::new(ADDRESS, Calibration::DATASHEET);
+        // normal mode, so ctrl_meas keeps its mode bits
+        let settings = SamplingSettings {
+            mode: Mode::Normal,
+            standby_time: Some(StandbyTime::Millis1000),
+            filter: Filter::X16,
+            pressure_oversampling: Oversampling::X2,
+            ..SamplingSettings::default()
+        };
+        sensor_with_settings(&device, settings.clone());
+
+        assert_eq!(
+            settings.registers(),
+            (
+                device.register(REGISTER_CONFIG),
+                device.register(REGISTER_CTRL_MEAS),
+                device.register(REGISTER_CTRL_HUM)
+            )
+        );
+    }
+
+    #[test]
+    fn info_reports_chip_and_settings() {
+        let device = FakeBme280::new(ADDRESS, Calibration::DATASHEET);
+        let bme280 = sensor(&device);
+
+        assert_eq!(
+            bme280.info(),
+            Some(SensorInfo {
+                variant: Variant::Bme280,
+                chip_id: bme280_rs::CHIP_ID,
+                address: ADDRESS,
+                settings: SamplingSettings::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn forced_measurement_reports_busy_and_returns_to_sleep() {
+        let device = FakeBme280::new(ADDRESS, Calibration::DATASHEET);
+        let mut bme280 = sensor(&device);
+        device.set_busy_polls(3);
+
+        bme280
+            .take_forced_measurement()
+            .expect("measurement failed");
+
+        assert_eq!(device.conversions(), 2);
+        assert_eq!(device.register(REGISTER_CTRL_MEAS) & 0b11, 0b00);
+
+        let mut status = [0];
+        device
+            .clone()
+            .write_read(ADDRESS, &[REGISTER_STATUS], &mut status)
+            .expect("failed to read status");
+        assert_ne!(status[0] & 0b1000, 0);
+    }
+
+    #[test]
+    fn read_sample_matches_datasheet_vector() {
+        let device = FakeBme280::new(ADDRESS, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        let mut bme280 = sensor(&device);
+
+        bme280
+            .take_forced_measurement()
+            .expect("measurement failed");
+        let reading = bme280.read_sample().expect("read failed");
+
+        // datasheet: T = 25.08 °C and P = 100653.27 Pa for these raw values
+        assert_eq!(reading.temperature, Some(25.08));
+        let pressure = reading.pressure.expect("pressure missing");
+        assert!((pressure - 100_653.27).abs() < 0.05, "pressure {pressure}");
+        // the floating point reference formula gives 51.9602 %
+        let humidity = reading.humidity.expect("humidity missing");
+        assert!((humidity - 51.9602).abs() < 0.01, "humidity {humidity}");
+        assert_eq!(
+            reading.raw,
+            Some(RawAdc {
+                temperature: Some(519_888),
+                pressure: Some(415_148),
+                humidity: Some(30_000),
+            })
+        );
+    }
+
+    #[test]
+    fn read_sample_exports_the_compensated_burst() {
+        let device = FakeBme280::new(ADDRESS, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        // the fake converts on every data read in normal mode
+        let mut bme280 = sensor_with_settings(
+            &device,
+            SamplingSettings {
+                mode: Mode::Normal,
+                ..SamplingSettings::default()
+            },
+        );
+
+        let conversions = device.conversions();
+        let reading = bme280.read_sample().expect("read failed");
+
+        assert_eq!(device.conversions(), conversions + 1);
+        assert_eq!(reading.temperature, Some(25.08));
+        let raw = reading.raw.expect("raw adc values missing");
+        assert_eq!(raw.temperature, Some(519_888));
+    }
+
+    #[test]
+    fn read_sample_skips_raw_adc_unless_asked() {
+        let device = FakeBme280::new(ADDRESS, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        let mut bme280 = Bme280Sensor::new(
+            device.clone(),
+            ADDRESS,
+            NoopDelay,
+            SamplingSettings::default(),
+        );
+        bme280.setup().expect("failed to setup fake bme280");
+
+        bme280
+            .take_forced_measurement()
+            .expect("measurement failed");
+        let reading = bme280.read_sample().expect("read failed");
+
+        assert_eq!(reading.temperature, Some(25.08));
+        assert_eq!(reading.raw, None);
+    }
+
+    #[test]
+    fn skipped_channels_read_as_none() {
+        let device = FakeBme280::new(ADDRESS, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        let mut bme280 = sensor_with_settings(
+            &device,
+            SamplingSettings {
+                temperature_oversampling: Oversampling::X1,
+                pressure_oversampling: Oversampling::Skip,
+                humidity_oversampling: Oversampling::Skip,
+                ..SamplingSettings::default()
+            },
+        );
+
+        bme280
+            .take_forced_measurement()
+            .expect("measurement failed");
+        let reading = bme280.read_sample().expect("read failed");
+
+        assert_eq!(reading.temperature, Some(25.08));
+        assert_eq!(reading.pressure, None);
+        assert_eq!(reading.humidity, None);
+        let raw = reading.raw.expect("raw adc values missing");
+        assert_eq!((raw.pressure, raw.humidity), (None, None));
+    }
+
+    #[test]
+    fn probe_finds_sensor_with_sdo_low() {
+        let mut device = FakeBme280::new(0x76, Calibration::DATASHEET);
+
+        assert_eq!(probe_address(&mut device), Some(0x76));
+    }
+
+    #[test]
+    fn probe_finds_bmp280() {
+        let mut device = FakeBme280::with_chip_id(0x77, 0x58, Calibration::DATASHEET);
+
+        assert_eq!(probe_address(&mut device), Some(0x77));
+    }
+
+    #[test]
+    fn probe_ignores_other_chips() {
+        let mut device = FakeBme280::with_chip_id(0x77, 0x42, Calibration::DATASHEET);
+
+        assert_eq!(probe_address(&mut device), None);
+    }
+
+    #[test]
+    fn parses_addresses() {
+        assert_eq!("auto".parse(), Ok(Address::Auto));
+        assert_eq!("0x76".parse(), Ok(Address::Fixed(0x76)));
+        assert_eq!("0X77".parse(), Ok(Address::Fixed(0x77)));
+        assert!("0x40".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn wrong_address_is_not_acknowledged() {
+        let device = FakeBme280::new(0x76, Calibration::DATASHEET);
+        let mut bme280 = Bme280::new_with_address(device, ADDRESS, NoopDelay);
+
+        assert!(bme280.chip_id().is_err());
+    }
+}
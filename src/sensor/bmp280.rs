@@ -0,0 +1,276 @@
+use super::{registers, Inspection, Reading, Sensor, SensorInfo, Variant};
+use crate::sampling::{Oversampling, SamplingSettings, StandbyTime};
+use anyhow::{bail, Context};
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Read, Write, WriteRead},
+};
+use tracing::info;
+
+const REGISTER_CTRL_MEAS: u8 = 0xF4;
+const REGISTER_CONFIG: u8 = 0xF5;
+const RESET_POLLS: usize = 10;
+
+/// The BME280 without its humidity sensor, which bme280-rs cannot drive as it
+/// insists on reading the humidity trimming parameters
+pub struct Bmp280Sensor<I2C, D> {
+    i2c: I2C,
+    address: u8,
+    delay: D,
+    settings: SamplingSettings,
+    /// Pass the ADC values on with the sample, they are read either way
+    raw_adc: bool,
+    /// Read during the setup
+    chip_id: Option<u8>,
+    trimming: Option<registers::Trimming>,
+}
+
+impl<I2C, D, E> Bmp280Sensor<I2C, D>
+where
+    I2C: Read<Error = E> + Write<Error = E> + WriteRead<Error = E>,
+    D: DelayMs<u32>,
+{
+    pub fn new(i2c: I2C, address: u8, delay: D, settings: SamplingSettings) -> Self {
+        Self {
+            i2c,
+            address,
+            delay,
+            settings: SamplingSettings {
+                humidity_oversampling: Oversampling::Skip,
+                ..settings
+            },
+            raw_adc: false,
+            chip_id: None,
+            trimming: None,
+        }
+    }
+
+    pub fn with_raw_adc(self, raw_adc: bool) -> Self {
+        Self { raw_adc, ..self }
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), E> {
+        self.i2c.write(self.address, &[register, value])
+    }
+}
+
+impl<I2C, D, E> Sensor for Bmp280Sensor<I2C, D>
+where
+    I2C: Read<Error = E> + Write<Error = E> + WriteRead<Error = E> + Send,
+    D: DelayMs<u32> + Send,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn setup(&mut self) -> anyhow::Result<()> {
+        // the codes for 10 and 20 ms mean 2000 and 4000 ms on the BMP280
+        if matches!(
+            self.settings.standby_time,
+            Some(StandbyTime::Millis10 | StandbyTime::Millis20)
+        ) {
+            bail!("standby times of 10 and 20 ms are not available on the bmp280");
+        }
+
+        info!("initializing bmp280 sensor");
+        let delay = &mut self.delay;
+        if !registers::reset(&mut self.i2c, self.address, RESET_POLLS, |ms| {
+            delay.delay_ms(ms)
+        })? {
+            bail!("bmp280 did not finish its reset");
+        }
+
+        let chip_id = registers::read_chip_id(&mut self.i2c, self.address)?;
+        if Variant::from_chip_id(chip_id) != Some(Variant::Bmp280) {
+            bail!("expected a bmp280, found chip id {chip_id:#04x}");
+        }
+        self.chip_id = Some(chip_id);
+        self.trimming = Some(registers::read_trimming(
+            &mut self.i2c,
+            self.address,
+            false,
+        )?);
+
+        info!("configuring bmp280 sensor");
+        let (config, ctrl_meas, _) = self.settings.registers();
+        // config is only writable in sleep mode
+        self.write_register(REGISTER_CTRL_MEAS, ctrl_meas & !0b11)?;
+        self.write_register(REGISTER_CONFIG, config)?;
+        self.write_register(REGISTER_CTRL_MEAS, ctrl_meas)?;
+
+        Ok(())
+    }
+
+    fn take_forced_measurement(&mut self) -> anyhow::Result<()> {
+        let (_, ctrl_meas, _) = self.settings.registers();
+        if ctrl_meas & 0b11 == 0b01 {
+            self.write_register(REGISTER_CTRL_MEAS, ctrl_meas)?;
+            std::thread::sleep(self.settings.measurement_time());
+        }
+
+        Ok(())
+    }
+
+    fn read_sample(&mut self) -> anyhow::Result<Reading> {
+        let trimming = self.trimming.context("bmp280 sensor is not set up")?;
+        let raw = registers::RawAdc {
+            // there are no humidity data registers, whatever answers is noise
+            humidity: None,
+            ..registers::read_raw_adc(&mut self.i2c, self.address)?
+        };
+
+        let t_fine = raw
+            .temperature
+            .map(|adc_t| fine_temperature(&trimming, adc_t));
+        let pressure = t_fine
+            .zip(raw.pressure)
+            .and_then(|(t_fine, adc_p)| compensate_pressure(&trimming, t_fine, adc_p));
+
+        Ok(Reading {
+            temperature: t_fine.map(|t_fine| (t_fine / 5120.0) as f32),
+            pressure: pressure.map(|pressure| pressure as f32),
+            humidity: None,
+            raw: self.raw_adc.then_some(raw),
+        })
+    }
+
+    fn inspect(&mut self) -> anyhow::Result<Option<Inspection>> {
+        Ok(Some(registers::inspect(
+            &mut self.i2c,
+            self.address,
+            false,
+        )?))
+    }
+
+    fn info(&self) -> Option<SensorInfo> {
+        Some(SensorInfo {
+            variant: Variant::Bmp280,
+            chip_id: self.chip_id?,
+            address: self.address,
+            settings: self.settings.clone(),
+        })
+    }
+}
+
+/// Temperature in 1/5120 °C, floating point formula from the BMP280 datasheet
+/// section 8.1
+fn fine_temperature(trimming: &registers::Trimming, adc_t: u32) -> f64 {
+    let adc_t = f64::from(adc_t);
+    let dig_t1 = f64::from(trimming.dig_t1);
+
+    let var1 = (adc_t / 16384.0 - dig_t1 / 1024.0) * f64::from(trimming.dig_t2);
+    let var2 = (adc_t / 131072.0 - dig_t1 / 8192.0).powi(2) * f64::from(trimming.dig_t3);
+    var1 + var2
+}
+
+/// Pressure in Pa, `None` when the trimming would divide by zero
+fn compensate_pressure(trimming: &registers::Trimming, t_fine: f64, adc_p: u32) -> Option<f64> {
+    let dig_p = [
+        trimming.dig_p2,
+        trimming.dig_p3,
+        trimming.dig_p4,
+        trimming.dig_p5,
+        trimming.dig_p6,
+        trimming.dig_p7,
+        trimming.dig_p8,
+        trimming.dig_p9,
+    ]
+    .map(f64::from);
+    let [p2, p3, p4, p5, p6, p7, p8, p9] = dig_p;
+
+    let mut var1 = t_fine / 2.0 - 64000.0;
+    let mut var2 = var1 * var1 * p6 / 32768.0;
+    var2 += var1 * p5 * 2.0;
+    var2 = var2 / 4.0 + p4 * 65536.0;
+    var1 = (p3 * var1 * var1 / 524_288.0 + p2 * var1) / 524_288.0;
+    var1 = (1.0 + var1 / 32768.0) * f64::from(trimming.dig_p1);
+    if var1 == 0.0 {
+        return None;
+    }
+
+    let mut pressure = 1_048_576.0 - f64::from(adc_p);
+    pressure = (pressure - var2 / 4096.0) * 6250.0 / var1;
+    var1 = p9 * pressure * pressure / 2_147_483_648.0;
+    var2 = pressure * p8 / 32768.0;
+    Some(pressure + (var1 + var2 + p7) / 16.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::fake::{
+        Calibration, FakeBme280, NoopDelay, REGISTER_CTRL_HUM, REGISTER_CTRL_MEAS,
+    };
+    use crate::sensor::RawAdc;
+
+    const ADDRESS: u8 = 0x76;
+
+    fn sensor(device: &FakeBme280) -> Bmp280Sensor<FakeBme280, NoopDelay> {
+        let mut sensor = Bmp280Sensor::new(
+            device.clone(),
+            ADDRESS,
+            NoopDelay,
+            SamplingSettings::default(),
+        )
+        .with_raw_adc(true);
+        sensor.setup().expect("failed to setup fake bmp280");
+        sensor
+    }
+
+    #[test]
+    fn read_sample_matches_datasheet_vector() {
+        let device = FakeBme280::with_chip_id(ADDRESS, 0x58, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        let mut bmp280 = sensor(&device);
+
+        bmp280
+            .take_forced_measurement()
+            .expect("measurement failed");
+        let reading = bmp280.read_sample().expect("read failed");
+
+        // datasheet: T = 25.08 °C and P = 100653.27 Pa for these raw values
+        let temperature = reading.temperature.expect("temperature missing");
+        assert!(
+            (temperature - 25.08).abs() < 0.005,
+            "temperature {temperature}"
+        );
+        let pressure = reading.pressure.expect("pressure missing");
+        assert!((pressure - 100_653.27).abs() < 0.05, "pressure {pressure}");
+        assert_eq!(reading.humidity, None);
+        assert_eq!(
+            reading.raw,
+            Some(RawAdc {
+                temperature: Some(519_888),
+                pressure: Some(415_148),
+                humidity: None,
+            })
+        );
+    }
+
+    #[test]
+    fn setup_leaves_humidity_alone() {
+        let device = FakeBme280::with_chip_id(ADDRESS, 0x58, Calibration::DATASHEET);
+        let bmp280 = sensor(&device);
+
+        assert_eq!(device.register(REGISTER_CTRL_HUM), 0);
+        // osrs_t = x8, osrs_p = x8, back to sleep after the forced conversion
+        assert_eq!(device.register(REGISTER_CTRL_MEAS), 0b100 << 5 | 0b100 << 2);
+        let info = bmp280.info().expect("info missing");
+        assert_eq!((info.variant, info.chip_id), (Variant::Bmp280, 0x58));
+        assert_eq!(info.settings.humidity_oversampling, Oversampling::Skip);
+    }
+
+    #[test]
+    fn rejects_standby_times_the_bmp280_reads_differently() {
+        let device = FakeBme280::with_chip_id(ADDRESS, 0x58, Calibration::DATASHEET);
+        let mut bmp280 = Bmp280Sensor::new(
+            device,
+            ADDRESS,
+            NoopDelay,
+            SamplingSettings {
+                mode: crate::sampling::Mode::Normal,
+                standby_time: Some(StandbyTime::Millis20),
+                ..SamplingSettings::default()
+            },
+        );
+
+        assert!(bmp280.setup().is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use super::registers::REGISTER_DATA;
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+use serde::Deserialize;
+use std::{
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// Device the sensor is wired to, `spi:<path>` for a spidev device and
+/// `i2c:<path>` or just the path for an I²C bus
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Bus {
+    I2c(PathBuf),
+    Spi(PathBuf),
+}
+
+impl FromStr for Bus {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let bus = match value.split_once(':') {
+            Some(("spi", path)) => Self::Spi(PathBuf::from(path)),
+            Some(("i2c", path)) => Self::I2c(PathBuf::from(path)),
+            _ => Self::I2c(PathBuf::from(value)),
+        };
+
+        match &bus {
+            Self::I2c(path) | Self::Spi(path) if path.as_os_str().is_empty() => {
+                Err(format!("expected a device path, got {value:?}"))
+            }
+            _ => Ok(bus),
+        }
+    }
+}
+
+impl TryFrom<String> for Bus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::I2c(path) => write!(f, "{}", path.display()),
+            Self::Spi(path) => write!(f, "spi:{}", path.display()),
+        }
+    }
+}
+
+/// I²C bus handle that can be cloned, so registers can be read next to the
+/// driver that owns the bus
+pub struct SharedBus<I2C> {
+    i2c: Arc<Mutex<I2C>>,
+    /// Last burst read of the data registers, the ADC values the driver
+    /// compensated its latest sample from
+    data: Arc<Mutex<Option<[u8; 8]>>>,
+}
+
+impl<I2C> SharedBus<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Self {
+            i2c: Arc::new(Mutex::new(i2c)),
+            data: Arc::default(),
+        }
+    }
+
+    /// Takes the data registers captured from the last burst read, if any
+    pub fn take_data(&self) -> Option<[u8; 8]> {
+        self.data.lock().expect("i2c bus poisoned").take()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, I2C> {
+        self.i2c.lock().expect("i2c bus poisoned")
+    }
+}
+
+impl<I2C> Clone for SharedBus<I2C> {
+    fn clone(&self) -> Self {
+        Self {
+            i2c: self.i2c.clone(),
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl<I2C: Read> Read for SharedBus<I2C> {
+    type Error = I2C::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.lock().read(address, buffer)
+    }
+}
+
+impl<I2C: Write> Write for SharedBus<I2C> {
+    type Error = I2C::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.lock().write(address, bytes)
+    }
+}
+
+impl<I2C: WriteRead> WriteRead for SharedBus<I2C> {
+    type Error = I2C::Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.lock().write_read(address, bytes, buffer)?;
+        if bytes == [REGISTER_DATA] {
+            if let Ok(data) = <[u8; 8]>::try_from(&*buffer) {
+                *self.data.lock().expect("i2c bus poisoned") = Some(data);
+            }
+        }
+        Ok(())
+    }
+}
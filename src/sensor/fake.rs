@@ -0,0 +1,385 @@
+//! Register-level emulation of a BME280 on an I²C or SPI bus, for tests
+
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Read, Write, WriteRead},
+    spi,
+};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+pub const REGISTER_CHIP_ID: u8 = 0xD0;
+pub const REGISTER_RESET: u8 = 0xE0;
+pub const REGISTER_CTRL_HUM: u8 = 0xF2;
+pub const REGISTER_STATUS: u8 = 0xF3;
+pub const REGISTER_CTRL_MEAS: u8 = 0xF4;
+pub const REGISTER_CONFIG: u8 = 0xF5;
+pub const REGISTER_DATA: u8 = 0xF7;
+
+const CALIBRATION_FIRST: u8 = 0x88;
+const CALIBRATION_SECOND: u8 = 0xE1;
+const RESET_COMMAND: u8 = 0xB6;
+const STATUS_MEASURING: u8 = 0b0000_1000;
+const SKIPPED_20BIT: u32 = 0x80000;
+const SKIPPED_16BIT: u16 = 0x8000;
+
+/// Trimming parameters as stored in the calibration NVM
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    pub dig_t: [i32; 3],
+    pub dig_p: [i32; 9],
+    pub dig_h: [i32; 6],
+}
+
+impl Calibration {
+    /// The worked example from the datasheet's compensation section, with
+    /// humidity parameters taken from a production part
+    pub const DATASHEET: Self = Self {
+        dig_t: [27504, 26435, -1000],
+        dig_p: [36477, -10685, 3024, 2855, 140, -7, 15500, -14600, 6000],
+        dig_h: [75, 362, 0, 324, 0, 30],
+    };
+
+    fn write_to(&self, memory: &mut [u8; 256]) {
+        let mut first = Vec::with_capacity(26);
+        for value in self.dig_t.iter().chain(&self.dig_p) {
+            first.extend_from_slice(&(*value as u16).to_le_bytes());
+        }
+        first.push(0);
+        first.push(self.dig_h[0] as u8);
+
+        let [_, h2, h3, h4, h5, h6] = self.dig_h;
+        let h2 = (h2 as i16).to_le_bytes();
+        let second = [
+            h2[0],
+            h2[1],
+            h3 as u8,
+            (h4 >> 4) as u8,
+            ((h4 & 0x0F) | ((h5 & 0x0F) << 4)) as u8,
+            (h5 >> 4) as u8,
+            h6 as u8,
+        ];
+
+        let first_start = usize::from(CALIBRATION_FIRST);
+        memory[first_start..first_start + first.len()].copy_from_slice(&first);
+        let second_start = usize::from(CALIBRATION_SECOND);
+        memory[second_start..second_start + second.len()].copy_from_slice(&second);
+    }
+}
+
+#[derive(Debug)]
+pub enum FakeError {
+    Nack(u8),
+}
+
+impl fmt::Display for FakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nack(address) => write!(f, "no acknowledge from address {address:#04x}"),
+        }
+    }
+}
+
+impl std::error::Error for FakeError {}
+
+struct Registers {
+    memory: [u8; 256],
+    pointer: u8,
+    ctrl_hum: u8,
+    adc: (u32, u32, u16),
+    busy_polls: usize,
+    busy_remaining: usize,
+    conversions: usize,
+    present: bool,
+}
+
+impl Registers {
+    fn reset(&mut self, chip_id: u8, calibration: &Calibration) {
+        self.memory = [0; 256];
+        self.memory[usize::from(REGISTER_CHIP_ID)] = chip_id;
+        calibration.write_to(&mut self.memory);
+        self.store_data(SKIPPED_20BIT, SKIPPED_20BIT, SKIPPED_16BIT);
+        self.ctrl_hum = 0;
+        self.busy_remaining = 0;
+    }
+
+    fn read(&mut self) -> u8 {
+        let register = self.pointer;
+        self.pointer = self.pointer.wrapping_add(1);
+
+        if register == REGISTER_STATUS && self.busy_remaining > 0 {
+            self.busy_remaining -= 1;
+            return self.memory[usize::from(register)] | STATUS_MEASURING;
+        }
+
+        // the chip shadows the data registers during a burst read, so a new
+        // normal mode conversion only shows up at the start of the next one
+        if register == REGISTER_DATA && self.mode() == 0b11 {
+            self.convert();
+        }
+
+        self.memory[usize::from(register)]
+    }
+
+    fn write(&mut self, register: u8, value: u8, chip_id: u8, calibration: &Calibration) {
+        match register {
+            REGISTER_RESET if value == RESET_COMMAND => self.reset(chip_id, calibration),
+            REGISTER_CTRL_HUM => self.memory[usize::from(register)] = value & 0b111,
+            REGISTER_CTRL_MEAS => {
+                // ctrl_hum only takes effect once ctrl_meas is written
+                self.ctrl_hum = self.memory[usize::from(REGISTER_CTRL_HUM)];
+                self.memory[usize::from(register)] = value;
+
+                if matches!(value & 0b11, 0b01 | 0b10) {
+                    self.convert();
+                    self.busy_remaining = self.busy_polls;
+                    self.memory[usize::from(register)] &= !0b11;
+                }
+            }
+            REGISTER_CONFIG => self.memory[usize::from(register)] = value,
+            _ => {}
+        }
+    }
+
+    fn mode(&self) -> u8 {
+        self.memory[usize::from(REGISTER_CTRL_MEAS)] & 0b11
+    }
+
+    fn convert(&mut self) {
+        let ctrl_meas = self.memory[usize::from(REGISTER_CTRL_MEAS)];
+        let (adc_t, adc_p, adc_h) = self.adc;
+
+        let adc_t = if ctrl_meas >> 5 == 0 {
+            SKIPPED_20BIT
+        } else {
+            adc_t
+        };
+        let adc_p = if (ctrl_meas >> 2) & 0b111 == 0 {
+            SKIPPED_20BIT
+        } else {
+            adc_p
+        };
+        let adc_h = if self.ctrl_hum == 0 {
+            SKIPPED_16BIT
+        } else {
+            adc_h
+        };
+
+        self.store_data(adc_t, adc_p, adc_h);
+        self.conversions += 1;
+    }
+
+    fn store_data(&mut self, adc_t: u32, adc_p: u32, adc_h: u16) {
+        let data = [
+            (adc_p >> 12) as u8,
+            (adc_p >> 4) as u8,
+            ((adc_p & 0x0F) << 4) as u8,
+            (adc_t >> 12) as u8,
+            (adc_t >> 4) as u8,
+            ((adc_t & 0x0F) << 4) as u8,
+            (adc_h >> 8) as u8,
+            adc_h as u8,
+        ];
+
+        let start = usize::from(REGISTER_DATA);
+        self.memory[start..start + data.len()].copy_from_slice(&data);
+    }
+}
+
+/// A BME280 answering on one I²C address
+///
+/// Clones share the same register file, so a test can keep a handle to
+/// inspect registers and feed ADC values after moving the device into a
+/// driver.
+#[derive(Clone)]
+pub struct FakeBme280 {
+    address: u8,
+    chip_id: u8,
+    calibration: Calibration,
+    registers: Arc<Mutex<Registers>>,
+}
+
+impl FakeBme280 {
+    pub fn new(address: u8, calibration: Calibration) -> Self {
+        Self::with_chip_id(address, bme280_rs::CHIP_ID, calibration)
+    }
+
+    pub fn with_chip_id(address: u8, chip_id: u8, calibration: Calibration) -> Self {
+        let mut registers = Registers {
+            memory: [0; 256],
+            pointer: 0,
+            ctrl_hum: 0,
+            adc: (SKIPPED_20BIT, SKIPPED_20BIT, SKIPPED_16BIT),
+            busy_polls: 1,
+            busy_remaining: 0,
+            conversions: 0,
+            present: true,
+        };
+        registers.reset(chip_id, &calibration);
+
+        Self {
+            address,
+            chip_id,
+            calibration,
+            registers: Arc::new(Mutex::new(registers)),
+        }
+    }
+
+    /// Set the raw ADC values latched by the next conversion
+    pub fn set_adc(&self, adc_t: u32, adc_p: u32, adc_h: u16) {
+        self.registers().adc = (adc_t, adc_p, adc_h);
+    }
+
+    /// Number of status reads that report a conversion in progress
+    pub fn set_busy_polls(&self, busy_polls: usize) {
+        self.registers().busy_polls = busy_polls;
+    }
+
+    /// Stop acknowledging any transfer, as if the cable came loose
+    pub fn unplug(&self) {
+        self.registers().present = false;
+    }
+
+    /// Reconnect the chip, which comes back with its power-on register values
+    pub fn plug_in(&self) {
+        let mut registers = self.registers();
+        registers.reset(self.chip_id, &self.calibration);
+        registers.present = true;
+    }
+
+    pub fn register(&self, register: u8) -> u8 {
+        self.registers().memory[usize::from(register)]
+    }
+
+    pub fn conversions(&self) -> usize {
+        self.registers().conversions
+    }
+
+    /// The same chip wired to an SPI bus
+    pub fn spi(&self) -> FakeSpi {
+        FakeSpi(self.clone())
+    }
+
+    fn registers(&self) -> std::sync::MutexGuard<'_, Registers> {
+        self.registers
+            .lock()
+            .expect("fake bme280 registers poisoned")
+    }
+
+    fn check_address(&self, address: u8) -> Result<(), FakeError> {
+        if address == self.address && self.registers().present {
+            Ok(())
+        } else {
+            Err(FakeError::Nack(address))
+        }
+    }
+}
+
+impl Write for FakeBme280 {
+    type Error = FakeError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check_address(address)?;
+
+        let mut registers = self.registers();
+        if let [pointer] = bytes {
+            registers.pointer = *pointer;
+        }
+
+        // writes are sent as (register, value) pairs, there is no auto-increment
+        for pair in bytes.chunks_exact(2) {
+            registers.write(pair[0], pair[1], self.chip_id, &self.calibration);
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for FakeBme280 {
+    type Error = FakeError;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_address(address)?;
+
+        let mut registers = self.registers();
+        for byte in buffer {
+            *byte = registers.read();
+        }
+
+        Ok(())
+    }
+}
+
+impl WriteRead for FakeBme280 {
+    type Error = FakeError;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.check_address(address)?;
+
+        let mut registers = self.registers();
+        registers.pointer = bytes.first().copied().unwrap_or(registers.pointer);
+        for byte in buffer {
+            *byte = registers.read();
+        }
+
+        Ok(())
+    }
+}
+
+/// SPI side of a [`FakeBme280`], which reads back 0xFF while unplugged as
+/// nothing drives the data line
+pub struct FakeSpi(FakeBme280);
+
+impl spi::Transfer<u8> for FakeSpi {
+    type Error = FakeError;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        let mut registers = self.0.registers();
+        if !registers.present {
+            words.fill(0xFF);
+            return Ok(words);
+        }
+
+        if let Some((control, data)) = words.split_first_mut() {
+            // bit 7 is the read bit, on the chip it is part of the address
+            if *control & 0x80 != 0 {
+                registers.pointer = *control;
+                for byte in data {
+                    *byte = registers.read();
+                }
+            }
+        }
+
+        Ok(words)
+    }
+}
+
+impl spi::Write<u8> for FakeSpi {
+    type Error = FakeError;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        let mut registers = self.0.registers();
+        if !registers.present {
+            return Ok(());
+        }
+
+        for pair in words.chunks_exact(2) {
+            registers.write(pair[0] | 0x80, pair[1], self.0.chip_id, &self.0.calibration);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct NoopDelay;
+
+impl DelayMs<u32> for NoopDelay {
+    fn delay_ms(&mut self, _ms: u32) {}
+}
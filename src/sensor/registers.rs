@@ -0,0 +1,264 @@
+//! Direct register access for debugging, next to the driver's own reads
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use serde::Serialize;
+
+const REGISTER_CALIBRATION_FIRST: u8 = 0x88;
+const REGISTER_CHIP_ID: u8 = 0xD0;
+const REGISTER_RESET: u8 = 0xE0;
+const REGISTER_CALIBRATION_SECOND: u8 = 0xE1;
+const REGISTER_CTRL_HUM: u8 = 0xF2;
+const REGISTER_STATUS: u8 = 0xF3;
+pub const REGISTER_DATA: u8 = 0xF7;
+const RESET_COMMAND: u8 = 0xB6;
+/// Set while the trimming parameters are copied to the image registers
+const STATUS_IM_UPDATE: u8 = 0b0000_0001;
+/// Value of a 20 bit ADC register when its channel was skipped
+const SKIPPED_20BIT: u32 = 0x80000;
+const SKIPPED_16BIT: u16 = 0x8000;
+
+/// Uncompensated ADC values of the last measurement, `None` for skipped
+/// channels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RawAdc {
+    pub temperature: Option<u32>,
+    pub pressure: Option<u32>,
+    pub humidity: Option<u16>,
+}
+
+/// Trimming parameters from the calibration NVM, datasheet section 4.2.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Trimming {
+    pub dig_t1: u16,
+    pub dig_t2: i16,
+    pub dig_t3: i16,
+    pub dig_p1: u16,
+    pub dig_p2: i16,
+    pub dig_p3: i16,
+    pub dig_p4: i16,
+    pub dig_p5: i16,
+    pub dig_p6: i16,
+    pub dig_p7: i16,
+    pub dig_p8: i16,
+    pub dig_p9: i16,
+    pub dig_h1: u8,
+    pub dig_h2: i16,
+    pub dig_h3: u8,
+    pub dig_h4: i16,
+    pub dig_h5: i16,
+    pub dig_h6: i8,
+}
+
+impl Trimming {
+    /// Parse registers 0x88 to 0xA1 and 0xE1 to 0xE7
+    fn parse(first: &[u8; 26], second: &[u8; 7]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([first[offset], first[offset + 1]]);
+        let i16_at = |offset: usize| u16_at(offset) as i16;
+
+        Self {
+            dig_t1: u16_at(0),
+            dig_t2: i16_at(2),
+            dig_t3: i16_at(4),
+            dig_p1: u16_at(6),
+            dig_p2: i16_at(8),
+            dig_p3: i16_at(10),
+            dig_p4: i16_at(12),
+            dig_p5: i16_at(14),
+            dig_p6: i16_at(16),
+            dig_p7: i16_at(18),
+            dig_p8: i16_at(20),
+            dig_p9: i16_at(22),
+            dig_h1: first[25],
+            dig_h2: i16::from_le_bytes([second[0], second[1]]),
+            dig_h3: second[2],
+            // 12 bit values sharing the nibbles of 0xE5
+            dig_h4: (i16::from(second[3] as i8) << 4) | i16::from(second[4] & 0x0F),
+            dig_h5: (i16::from(second[5] as i8) << 4) | i16::from(second[4] >> 4),
+            dig_h6: second[6] as i8,
+        }
+    }
+}
+
+/// Control and status registers as currently set on the chip
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct ControlRegisters {
+    pub ctrl_hum: u8,
+    pub status: u8,
+    pub ctrl_meas: u8,
+    pub config: u8,
+}
+
+/// Everything needed to redo the compensation by hand
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Inspection {
+    pub chip_id: u8,
+    pub address: u8,
+    pub registers: ControlRegisters,
+    pub trimming: Trimming,
+}
+
+pub fn read_raw_adc<I2C: WriteRead>(i2c: &mut I2C, address: u8) -> Result<RawAdc, I2C::Error> {
+    let mut data = [0; 8];
+    i2c.write_read(address, &[REGISTER_DATA], &mut data)?;
+    Ok(parse_raw_adc(&data))
+}
+
+/// Splits a burst read of the data registers into the ADC values
+pub fn parse_raw_adc(data: &[u8; 8]) -> RawAdc {
+    let twenty_bits = |msb: u8, lsb: u8, xlsb: u8| {
+        (u32::from(msb) << 12) | (u32::from(lsb) << 4) | (u32::from(xlsb) >> 4)
+    };
+    let pressure = twenty_bits(data[0], data[1], data[2]);
+    let temperature = twenty_bits(data[3], data[4], data[5]);
+    let humidity = u16::from_be_bytes([data[6], data[7]]);
+
+    RawAdc {
+        temperature: Some(temperature).filter(|&adc| adc != SKIPPED_20BIT),
+        pressure: Some(pressure).filter(|&adc| adc != SKIPPED_20BIT),
+        humidity: Some(humidity).filter(|&adc| adc != SKIPPED_16BIT),
+    }
+}
+
+/// Soft-reset the chip and wait for it to copy its trimming parameters,
+/// `false` if it was still copying after `polls` status reads
+pub fn reset<I2C, E>(
+    i2c: &mut I2C,
+    address: u8,
+    polls: usize,
+    mut delay_ms: impl FnMut(u32),
+) -> Result<bool, E>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    i2c.write(address, &[REGISTER_RESET, RESET_COMMAND])?;
+    delay_ms(2);
+
+    for _ in 0..polls {
+        let mut status = [0];
+        i2c.write_read(address, &[REGISTER_STATUS], &mut status)?;
+        if status[0] & STATUS_IM_UPDATE == 0 {
+            return Ok(true);
+        }
+        delay_ms(1);
+    }
+
+    Ok(false)
+}
+
+pub fn read_chip_id<I2C: WriteRead>(i2c: &mut I2C, address: u8) -> Result<u8, I2C::Error> {
+    let mut chip_id = [0];
+    i2c.write_read(address, &[REGISTER_CHIP_ID], &mut chip_id)?;
+
+    Ok(chip_id[0])
+}
+
+/// Read the trimming parameters, the humidity ones are left at 0 for chips
+/// without a humidity sensor
+pub fn read_trimming<I2C: WriteRead>(
+    i2c: &mut I2C,
+    address: u8,
+    humidity: bool,
+) -> Result<Trimming, I2C::Error> {
+    let mut first = [0; 26];
+    i2c.write_read(address, &[REGISTER_CALIBRATION_FIRST], &mut first)?;
+    let mut second = [0; 7];
+    if humidity {
+        i2c.write_read(address, &[REGISTER_CALIBRATION_SECOND], &mut second)?;
+    } else {
+        // 0xA1 holds dig_H1 on the BME280 and is unused on the BMP280
+        first[25] = 0;
+    }
+
+    Ok(Trimming::parse(&first, &second))
+}
+
+pub fn inspect<I2C: WriteRead>(
+    i2c: &mut I2C,
+    address: u8,
+    humidity: bool,
+) -> Result<Inspection, I2C::Error> {
+    let chip_id = read_chip_id(i2c, address)?;
+    let trimming = read_trimming(i2c, address, humidity)?;
+
+    // ctrl_hum, status, ctrl_meas and config are consecutive
+    let mut control = [0; 4];
+    i2c.write_read(address, &[REGISTER_CTRL_HUM], &mut control)?;
+
+    Ok(Inspection {
+        chip_id,
+        address,
+        registers: ControlRegisters {
+            ctrl_hum: control[0],
+            status: control[1],
+            ctrl_meas: control[2],
+            config: control[3],
+        },
+        trimming,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::fake::{Calibration, FakeBme280};
+
+    #[test]
+    fn reads_trimming_parameters() {
+        let mut device = FakeBme280::new(0x76, Calibration::DATASHEET);
+
+        let inspection = inspect(&mut device, 0x76, true).expect("failed to inspect");
+
+        assert_eq!(inspection.chip_id, 0x60);
+        let trimming = inspection.trimming;
+        assert_eq!(
+            (trimming.dig_t1, trimming.dig_t2, trimming.dig_t3),
+            (27504, 26435, -1000)
+        );
+        assert_eq!((trimming.dig_p1, trimming.dig_p2), (36477, -10685));
+        assert_eq!((trimming.dig_p8, trimming.dig_p9), (-14600, 6000));
+        assert_eq!(
+            (
+                trimming.dig_h1,
+                trimming.dig_h2,
+                trimming.dig_h3,
+                trimming.dig_h4,
+                trimming.dig_h5,
+                trimming.dig_h6
+            ),
+            (75, 362, 0, 324, 0, 30)
+        );
+    }
+
+    #[test]
+    fn splits_shared_humidity_nibbles() {
+        let mut second = [0; 7];
+        // dig_H4 = -300 (0xED4) and dig_H5 = 1234 (0x4D2)
+        second[3] = 0xED;
+        second[4] = 0x24;
+        second[5] = 0x4D;
+
+        let trimming = Trimming::parse(&[0; 26], &second);
+        assert_eq!((trimming.dig_h4, trimming.dig_h5), (-300, 1234));
+    }
+
+    #[test]
+    fn bmp280_has_no_humidity_trimming() {
+        let mut device = FakeBme280::with_chip_id(0x76, 0x58, Calibration::DATASHEET);
+
+        let trimming = read_trimming(&mut device, 0x76, false).expect("failed to read trimming");
+
+        assert_eq!((trimming.dig_t1, trimming.dig_p9), (27504, 6000));
+        assert_eq!(
+            (trimming.dig_h1, trimming.dig_h2, trimming.dig_h6),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn skipped_channels_have_no_raw_value() {
+        let mut device = FakeBme280::new(0x76, Calibration::DATASHEET);
+
+        let raw = read_raw_adc(&mut device, 0x76).expect("failed to read adc");
+        assert_eq!(raw, RawAdc::default());
+    }
+}
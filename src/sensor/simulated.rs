@@ -0,0 +1,71 @@
+use super::{Reading, Sensor};
+use std::{
+    f32::consts::TAU,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+const PERIOD_SECONDS: f32 = 3600.0;
+
+/// Produces readings that slowly drift around a baseline with a bit of noise
+pub struct SimulatedSensor {
+    temperature: f32,
+    pressure: f32,
+    humidity: f32,
+    started: Instant,
+    rng: u64,
+    sample: Option<Reading>,
+}
+
+impl SimulatedSensor {
+    pub fn new(temperature: f32, pressure: f32, humidity: f32) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default();
+
+        Self {
+            temperature,
+            pressure,
+            humidity,
+            started: Instant::now(),
+            rng: seed | 1,
+            sample: None,
+        }
+    }
+
+    fn noise(&mut self) -> f32 {
+        // xorshift64, good enough for jitter
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+
+        (self.rng >> 40) as f32 / (1u64 << 24) as f32 * 2.0 - 1.0
+    }
+}
+
+impl Sensor for SimulatedSensor {
+    fn take_forced_measurement(&mut self) -> anyhow::Result<()> {
+        let phase = (self.started.elapsed().as_secs_f32() / PERIOD_SECONDS * TAU).sin();
+
+        let temperature = self.temperature + phase + 0.05 * self.noise();
+        let pressure = self.pressure - 150.0 * phase + 5.0 * self.noise();
+        let humidity = self.humidity - 5.0 * phase + 0.5 * self.noise();
+
+        self.sample = Some(Reading {
+            temperature: Some(temperature),
+            pressure: Some(pressure),
+            humidity: Some(humidity.clamp(0.0, 100.0)),
+            raw: None,
+        });
+
+        Ok(())
+    }
+
+    fn read_sample(&mut self) -> anyhow::Result<Reading> {
+        if self.sample.is_none() {
+            self.take_forced_measurement()?;
+        }
+
+        Ok(self.sample.unwrap_or_default())
+    }
+}
@@ -0,0 +1,208 @@
+//! Register access over SPI, behind the I²C traits the drivers are written
+//! against
+
+use anyhow::Context;
+use embedded_hal::blocking::{i2c, spi};
+use linux_embedded_hal::{
+    spidev::{SpiModeFlags, SpidevOptions},
+    Spidev,
+};
+use std::{fmt, path::Path};
+
+/// Well below the 10 MHz maximum, for longer cable runs
+const MAX_SPEED_HZ: u32 = 1_000_000;
+/// Bit 7 of the control byte, the other bits are the register address
+const READ: u8 = 0x80;
+const REGISTER_STATUS: u8 = 0xF3;
+const REGISTER_DATA: u8 = 0xF7;
+
+#[derive(Debug)]
+pub enum SpiError<E> {
+    Transfer(E),
+    /// Registers with bits that always read 0 came back all ones, as the
+    /// data line does with no sensor driving it
+    NoSensor,
+}
+
+impl<E> fmt::Display for SpiError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transfer(_) => write!(f, "spi transfer failed"),
+            Self::NoSensor => write!(f, "no sensor answered on the spi bus"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for SpiError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transfer(error) => Some(error),
+            Self::NoSensor => None,
+        }
+    }
+}
+
+/// Translates I²C register reads and writes into SPI transfers
+///
+/// The I²C address is ignored, the chip select picks the sensor.
+pub struct SpiInterface<SPI> {
+    spi: SPI,
+    /// Register set by a write without data, for the next read
+    pointer: u8,
+}
+
+impl<SPI> SpiInterface<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        Self { spi, pointer: 0 }
+    }
+}
+
+/// Open a spidev device in SPI mode 0, which the sensor selects when chip
+/// select goes low with the clock idle low
+pub fn open(path: &Path) -> anyhow::Result<SpiInterface<Spidev>> {
+    let mut spi = Spidev::open(path)
+        .with_context(|| format!("failed to open spi device {}", path.display()))?;
+    spi.configure(
+        &SpidevOptions::new()
+            .bits_per_word(8)
+            .max_speed_hz(MAX_SPEED_HZ)
+            .mode(SpiModeFlags::SPI_MODE_0)
+            .build(),
+    )
+    .with_context(|| format!("failed to configure spi device {}", path.display()))?;
+
+    Ok(SpiInterface::new(spi))
+}
+
+impl<SPI: spi::Write<u8>> i2c::Write for SpiInterface<SPI> {
+    type Error = SpiError<SPI::Error>;
+
+    fn write(&mut self, _address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        if let [pointer] = bytes {
+            self.pointer = *pointer;
+            return Ok(());
+        }
+
+        // register and value pairs, with the read bit cleared
+        let frame: Vec<u8> = bytes
+            .chunks_exact(2)
+            .flat_map(|pair| [pair[0] & !READ, pair[1]])
+            .collect();
+        self.spi.write(&frame).map_err(SpiError::Transfer)
+    }
+}
+
+impl<SPI: spi::Transfer<u8>> i2c::Read for SpiInterface<SPI> {
+    type Error = SpiError<SPI::Error>;
+
+    fn read(&mut self, _address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        // the sensor answers from the second byte on, incrementing the
+        // register address itself
+        let mut frame = vec![0; buffer.len() + 1];
+        frame[0] = self.pointer | READ;
+        let received = &self.spi.transfer(&mut frame).map_err(SpiError::Transfer)?[1..];
+
+        // unlike on i2c, a missing sensor does not fail the transfer, and the
+        // drivers would poll a status of all ones forever
+        let floating = received.iter().all(|&byte| byte == 0xFF);
+        if floating && matches!(self.pointer | READ, REGISTER_STATUS | REGISTER_DATA) {
+            return Err(SpiError::NoSensor);
+        }
+        buffer.copy_from_slice(received);
+
+        Ok(())
+    }
+}
+
+impl<SPI: spi::Transfer<u8>> i2c::WriteRead for SpiInterface<SPI> {
+    type Error = SpiError<SPI::Error>;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        if let Some(&pointer) = bytes.first() {
+            self.pointer = pointer;
+        }
+        i2c::Read::read(self, address, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::SamplingSettings;
+    use crate::sensor::{
+        self,
+        fake::{Calibration, FakeBme280, NoopDelay, REGISTER_CONFIG},
+    };
+
+    #[test]
+    fn measures_over_spi() {
+        let device = FakeBme280::new(0x76, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        let spi = SpiInterface::new(device.spi());
+
+        let mut bme280 = sensor::open(spi, 0, NoopDelay, &SamplingSettings::default(), false)
+            .expect("failed to detect sensor over spi");
+        bme280.setup().expect("failed to setup sensor over spi");
+        bme280
+            .take_forced_measurement()
+            .expect("measurement failed");
+        let reading = bme280.read_sample().expect("read failed");
+
+        // t_sb = 0.5 ms, filter = 4
+        assert_eq!(device.register(REGISTER_CONFIG), 0b010 << 2);
+        assert_eq!(reading.temperature, Some(25.08));
+        let humidity = reading.humidity.expect("humidity missing");
+        assert!((humidity - 51.9602).abs() < 0.01, "humidity {humidity}");
+    }
+
+    #[test]
+    fn unplugged_sensor_fails_instead_of_reading_ones() {
+        let device = FakeBme280::new(0x76, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        let mut bme280 = sensor::open(
+            SpiInterface::new(device.spi()),
+            0,
+            NoopDelay,
+            &SamplingSettings::default(),
+            false,
+        )
+        .expect("failed to detect sensor over spi");
+        bme280.setup().expect("failed to setup sensor over spi");
+
+        device.unplug();
+        let err = bme280
+            .take_forced_measurement()
+            .and_then(|()| bme280.read_sample())
+            .expect_err("unplugged sensor should fail");
+        assert!(err.to_string().contains("no sensor answered"), "{err:#}");
+        let err = bme280.setup().expect_err("unplugged sensor should fail");
+        assert!(err.to_string().contains("chip id 0xff"), "{err:#}");
+
+        let spi = SpiInterface::new(device.spi());
+        assert!(sensor::open(spi, 0, NoopDelay, &SamplingSettings::default(), false).is_err());
+    }
+
+    #[test]
+    fn reads_bmp280_over_spi() {
+        let device = FakeBme280::with_chip_id(0x76, 0x58, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        let spi = SpiInterface::new(device.spi());
+
+        let mut bmp280 = sensor::open(spi, 0, NoopDelay, &SamplingSettings::default(), false)
+            .expect("failed to detect sensor over spi");
+        bmp280.setup().expect("failed to setup sensor over spi");
+        bmp280
+            .take_forced_measurement()
+            .expect("measurement failed");
+        let reading = bmp280.read_sample().expect("read failed");
+
+        let pressure = reading.pressure.expect("pressure missing");
+        assert!((pressure - 100_653.27).abs() < 0.05, "pressure {pressure}");
+        assert_eq!(reading.humidity, None);
+    }
+}
@@ -0,0 +1,147 @@
+//! Accumulated values that survive restarts, kept in a JSON file
+
+use crate::degree_days::{Accumulator, GrowingSeason};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{ErrorKind, Write},
+    path::Path,
+};
+use tracing::warn;
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct State {
+    /// Keyed by sensor name
+    pub sensors: BTreeMap<String, SensorState>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct SensorState {
+    pub growing_season: Option<GrowingSeason>,
+    /// Degrees below the heating base temperature
+    pub heating: Accumulator,
+    /// Degrees above the cooling base temperature
+    pub cooling: Accumulator,
+}
+
+impl State {
+    /// A missing file is an empty state, as on the first start, and so is a
+    /// corrupt one after moving it aside
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => {
+                return Err(error).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+
+        let error = match serde_json::from_str(&contents) {
+            Ok(state) => return Ok(state),
+            Err(error) => error,
+        };
+        let aside = path.with_extension("corrupt");
+        std::fs::rename(path, &aside)
+            .with_context(|| format!("failed to move {} aside", path.display()))?;
+        warn!(
+            error = format!("{error:#}"),
+            moved_to = %aside.display(),
+            "corrupt state file, starting over"
+        );
+
+        Ok(Self::default())
+    }
+
+    /// Write to a temporary file first, so a crash never leaves a truncated
+    /// state behind, and sync it before and the directory after the rename,
+    /// so a power cut does not either
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        let temporary = path.with_extension("tmp");
+
+        let mut file = File::create(&temporary)
+            .with_context(|| format!("failed to create {}", temporary.display()))?;
+        file.write_all(contents.as_bytes())
+            .and_then(|()| file.sync_all())
+            .with_context(|| format!("failed to write {}", temporary.display()))?;
+        std::fs::rename(&temporary, path)
+            .with_context(|| format!("failed to replace {}", path.display()))?;
+
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(directory)
+            .and_then(|directory| directory.sync_all())
+            .with_context(|| format!("failed to sync {}", directory.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_file() {
+        let path = std::env::temp_dir().join(format!("bme280-state-{}.json", std::process::id()));
+        let mut state = State::default();
+        state.sensors.insert(
+            "greenhouse".to_owned(),
+            SensorState {
+                growing_season: Some(GrowingSeason {
+                    started: 20_544,
+                    accumulator: Accumulator {
+                        degree_seconds: 86_400.0 * 412.5,
+                        updated: Some(1_792_339_200.0),
+                    },
+                }),
+                heating: Accumulator {
+                    degree_seconds: 3_600.0,
+                    updated: Some(1_792_339_200.0),
+                },
+                ..SensorState::default()
+            },
+        );
+
+        state.save(&path).expect("failed to save state");
+        let loaded = State::load(&path).expect("failed to load state");
+        std::fs::remove_file(&path).expect("failed to remove state");
+
+        assert_eq!(loaded, state);
+    }
+
+    #[test]
+    fn loads_state_without_accumulators() {
+        let state: State =
+            serde_json::from_str(r#"{"sensors": {"attic": {}}}"#).expect("failed to parse state");
+
+        assert_eq!(state.sensors["attic"], SensorState::default());
+    }
+
+    #[test]
+    fn corrupt_file_is_moved_aside() {
+        let path = std::env::temp_dir().join(format!("bme280-corrupt-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"sensors": {"attic": "#).expect("failed to write state");
+
+        let state = State::load(&path).expect("corrupt file should load");
+        let aside = std::fs::read_to_string(path.with_extension("corrupt"))
+            .expect("corrupt file was not moved aside");
+        std::fs::remove_file(path.with_extension("corrupt")).expect("failed to remove state");
+
+        assert_eq!(state, State::default());
+        assert_eq!(aside, r#"{"sensors": {"attic": "#);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn missing_file_is_empty_state() {
+        let state = State::load(Path::new("/nonexistent/bme280-state.json"))
+            .expect("missing file should load");
+
+        assert_eq!(state, State::default());
+    }
+}
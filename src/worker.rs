@@ -0,0 +1,566 @@
+use crate::sensor::{Inspection, Reading, Sensor, SensorInfo};
+use std::{
+    fmt,
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tracing::{info, warn};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+const REINIT_AFTER_FAILURES: u32 = 3;
+
+enum Request {
+    Measure(oneshot::Sender<Result<Reading, Failure>>),
+    Inspect(oneshot::Sender<Result<Option<Inspection>, Failure>>),
+}
+
+/// Step of talking to a sensor that failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Init,
+    Measure,
+    Read,
+}
+
+impl Stage {
+    pub const ALL: [Self; 3] = [Self::Init, Self::Measure, Self::Read];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Init => "init",
+            Self::Measure => "measure",
+            Self::Read => "read",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Failure {
+    /// Talking to the sensor failed
+    Sensor { stage: Stage, error: anyhow::Error },
+    /// The sensor is not connected and waits out its backoff, the bus was
+    /// not touched
+    Backoff { retry_in: Duration },
+    /// Other requests held the sensor for longer than the timeout, this one
+    /// never reached it
+    Busy,
+}
+
+impl Failure {
+    fn new(stage: Stage, error: anyhow::Error) -> Self {
+        Self::Sensor { stage, error }
+    }
+
+    /// `None` when the sensor was not asked
+    pub fn stage(&self) -> Option<Stage> {
+        match self {
+            Self::Sensor { stage, .. } => Some(*stage),
+            Self::Backoff { .. } | Self::Busy => None,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sensor { stage, error } => write!(f, "{} failed: {:#}", stage.as_str(), error),
+            Self::Backoff { retry_in } => {
+                write!(f, "sensor is not initialised, retrying in {retry_in:?}")
+            }
+            Self::Busy => write!(f, "sensor is busy with other requests"),
+        }
+    }
+}
+
+/// Keeps a sensor connected, opening the bus and initialising the chip with
+/// exponential backoff until it answers
+struct Supervisor<C> {
+    name: String,
+    connect: C,
+    sensor: Option<Box<dyn Sensor>>,
+    /// Info of the connected sensor, shared with the worker handle
+    info: Arc<Mutex<Option<SensorInfo>>>,
+    failures: u32,
+    backoff: Duration,
+    retry_at: Instant,
+}
+
+impl<C> Supervisor<C>
+where
+    C: FnMut() -> anyhow::Result<Box<dyn Sensor>>,
+{
+    fn new(name: &str, connect: C) -> Self {
+        Self {
+            name: name.to_owned(),
+            connect,
+            sensor: None,
+            info: Default::default(),
+            failures: 0,
+            backoff: MIN_BACKOFF,
+            retry_at: Instant::now(),
+        }
+    }
+
+    fn measure(&mut self) -> Result<Reading, Failure> {
+        let sensor = self.sensor()?;
+
+        let reading = sensor
+            .take_forced_measurement()
+            .map_err(|err| Failure::new(Stage::Measure, err))
+            .and_then(|()| {
+                sensor
+                    .read_sample()
+                    .map_err(|err| Failure::new(Stage::Read, err))
+            });
+
+        match &reading {
+            Ok(_) => self.failures = 0,
+            Err(_) => {
+                self.failures += 1;
+                if self.failures >= REINIT_AFTER_FAILURES {
+                    self.reinitialise();
+                }
+            }
+        }
+
+        reading
+    }
+
+    /// Register dumps are for debugging and do not count towards
+    /// re-initialisation
+    fn inspect(&mut self) -> Result<Option<Inspection>, Failure> {
+        self.sensor()?
+            .inspect()
+            .map_err(|err| Failure::new(Stage::Read, err))
+    }
+
+    fn sensor(&mut self) -> Result<&mut Box<dyn Sensor>, Failure> {
+        if self.sensor.is_none() {
+            let now = Instant::now();
+            if now < self.retry_at {
+                return Err(Failure::Backoff {
+                    retry_in: self.retry_at - now,
+                });
+            }
+
+            match (self.connect)() {
+                Ok(sensor) => {
+                    info!(sensor = self.name, "sensor initialised");
+                    self.publish_info(sensor.info());
+                    self.sensor = Some(sensor);
+                    self.backoff = MIN_BACKOFF;
+                    self.failures = 0;
+                }
+                Err(err) => {
+                    self.schedule_retry(now);
+                    return Err(Failure::new(Stage::Init, err));
+                }
+            }
+        }
+
+        Ok(self.sensor.as_mut().expect("sensor was just connected"))
+    }
+
+    /// Re-run the setup after repeated failures, the chip may have been
+    /// replugged and lost its configuration
+    fn reinitialise(&mut self) {
+        let Some(sensor) = &mut self.sensor else {
+            return;
+        };
+
+        warn!(
+            sensor = self.name,
+            failures = self.failures,
+            "re-initialising sensor after repeated failures"
+        );
+        self.failures = 0;
+
+        match sensor.setup() {
+            Ok(()) => {
+                let info = sensor.info();
+                self.publish_info(info);
+            }
+            Err(err) => {
+                // start over with opening the bus
+                warn!(
+                    sensor = self.name,
+                    error = format!("{err:#}"),
+                    "failed to re-initialise sensor"
+                );
+                self.sensor = None;
+                self.publish_info(None);
+                self.schedule_retry(Instant::now());
+            }
+        }
+    }
+
+    fn publish_info(&self, info: Option<SensorInfo>) {
+        *self.info.lock().expect("sensor info poisoned") = info;
+    }
+
+    fn schedule_retry(&mut self, now: Instant) {
+        warn!(
+            sensor = self.name,
+            retry_in = ?self.backoff,
+            "sensor is not available"
+        );
+        self.retry_at = now + self.backoff;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Runs the blocking bus I/O of a sensor on a dedicated thread
+pub struct SensorWorker {
+    name: String,
+    requests: SyncSender<Request>,
+    /// Held for the whole request, so overlapping requests such as two
+    /// scrapes wait their turn instead of being turned away
+    turn: AsyncMutex<()>,
+    info: Arc<Mutex<Option<SensorInfo>>>,
+    timeout: Duration,
+}
+
+impl SensorWorker {
+    /// Spawn the worker thread, `connect` opens the bus and sets up the
+    /// sensor and is retried until it succeeds
+    pub fn spawn<C>(name: &str, connect: C, timeout: Duration) -> Self
+    where
+        C: FnMut() -> anyhow::Result<Box<dyn Sensor>> + Send + 'static,
+    {
+        // at most one request waits behind the one in progress, so a hung
+        // bus does not pile up requests
+        let (requests, receiver) = mpsc::sync_channel::<Request>(1);
+        let mut supervisor = Supervisor::new(name, connect);
+        let info = supervisor.info.clone();
+
+        thread::Builder::new()
+            .name(format!("sensor-{name}"))
+            .spawn(move || {
+                // the requester is gone when it timed out
+                for request in receiver {
+                    match request {
+                        Request::Measure(reply) => {
+                            let _ = reply.send(supervisor.measure());
+                        }
+                        Request::Inspect(reply) => {
+                            let _ = reply.send(supervisor.inspect());
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn sensor thread");
+
+        Self {
+            name: name.to_owned(),
+            requests,
+            turn: AsyncMutex::new(()),
+            info,
+            timeout,
+        }
+    }
+
+    /// Info of the sensor as of its last (re)connect
+    pub fn info(&self) -> Option<SensorInfo> {
+        self.info.lock().expect("sensor info poisoned").clone()
+    }
+
+    pub async fn measure(&self) -> Result<Reading, Failure> {
+        self.request(Request::Measure).await
+    }
+
+    pub async fn inspect(&self) -> Result<Option<Inspection>, Failure> {
+        self.request(Request::Inspect).await
+    }
+
+    async fn request<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<Result<T, Failure>>) -> Request,
+    ) -> Result<T, Failure> {
+        let Ok(_turn) = tokio::time::timeout(self.timeout, self.turn.lock()).await else {
+            return Err(Failure::Busy);
+        };
+        let (reply, response) = oneshot::channel();
+
+        // a request that never completes is counted against the measure stage
+        let failure = |error| Failure::new(Stage::Measure, error);
+
+        match self.requests.try_send(request(reply)) {
+            Ok(()) => {}
+            // only when earlier requests timed out and the thread is stuck
+            Err(TrySendError::Full(_)) => {
+                return Err(failure(anyhow::anyhow!(
+                    "sensor {} is still busy with earlier reads",
+                    self.name
+                )))
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                return Err(failure(anyhow::anyhow!(
+                    "sensor {} thread has stopped",
+                    self.name
+                )))
+            }
+        }
+
+        match tokio::time::timeout(self.timeout, response).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => Err(failure(anyhow::anyhow!(
+                "sensor {} thread has stopped",
+                self.name
+            ))),
+            Err(_) => Err(failure(anyhow::anyhow!(
+                "sensor {} did not respond within {:?}",
+                self.name,
+                self.timeout
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sampling::SamplingSettings,
+        sensor::{
+            fake::{Calibration, FakeBme280, NoopDelay, REGISTER_CTRL_HUM},
+            Bme280Sensor,
+        },
+    };
+    use anyhow::Context;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::Receiver,
+        Arc,
+    };
+
+    fn connected(
+        sensor: impl Sensor + 'static,
+    ) -> impl FnMut() -> anyhow::Result<Box<dyn Sensor>> + Send + 'static {
+        let mut sensor = Some(Box::new(sensor) as Box<dyn Sensor>);
+        move || sensor.take().context("sensor was already handed out")
+    }
+
+    fn fake_bme280(
+        device: &FakeBme280,
+        attempts: &Arc<AtomicUsize>,
+    ) -> impl FnMut() -> anyhow::Result<Box<dyn Sensor>> + Send + 'static {
+        let (device, attempts) = (device.clone(), attempts.clone());
+        move || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            let mut sensor =
+                Bme280Sensor::new(device.clone(), 0x76, NoopDelay, SamplingSettings::default());
+            sensor.setup()?;
+            Ok(Box::new(sensor))
+        }
+    }
+
+    struct HungSensor(Receiver<()>);
+
+    impl Sensor for HungSensor {
+        fn take_forced_measurement(&mut self) -> anyhow::Result<()> {
+            // blocks until the test drops the sender
+            let _ = self.0.recv();
+            Ok(())
+        }
+
+        fn read_sample(&mut self) -> anyhow::Result<Reading> {
+            Ok(Reading::default())
+        }
+    }
+
+    struct FixedSensor(Reading);
+
+    impl Sensor for FixedSensor {
+        fn take_forced_measurement(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn read_sample(&mut self) -> anyhow::Result<Reading> {
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn measures_on_sensor_thread() {
+        let reading = Reading {
+            temperature: Some(25.08),
+            ..Reading::default()
+        };
+        let worker = SensorWorker::spawn(
+            "fixed",
+            connected(FixedSensor(reading)),
+            Duration::from_secs(1),
+        );
+
+        for _ in 0..3 {
+            assert_eq!(worker.measure().await.expect("measure failed"), reading);
+        }
+    }
+
+    struct SlowSensor;
+
+    impl Sensor for SlowSensor {
+        fn take_forced_measurement(&mut self) -> anyhow::Result<()> {
+            thread::sleep(Duration::from_millis(20));
+            Ok(())
+        }
+
+        fn read_sample(&mut self) -> anyhow::Result<Reading> {
+            Ok(Reading::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn overlapping_requests_wait_their_turn() {
+        let worker = SensorWorker::spawn("slow", connected(SlowSensor), Duration::from_secs(1));
+
+        let (first, second, third) =
+            tokio::join!(worker.measure(), worker.measure(), worker.inspect());
+        first.expect("first measure failed");
+        second.expect("overlapping measure failed");
+        third.expect("overlapping inspect failed");
+    }
+
+    #[tokio::test]
+    async fn inspects_on_sensor_thread() {
+        let device = FakeBme280::new(0x76, Calibration::DATASHEET);
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let worker = SensorWorker::spawn(
+            "inspected",
+            fake_bme280(&device, &attempts),
+            Duration::from_secs(1),
+        );
+
+        let inspection = worker
+            .inspect()
+            .await
+            .expect("inspect failed")
+            .expect("bme280 has registers");
+        assert_eq!((inspection.chip_id, inspection.address), (0x60, 0x76));
+        assert_eq!(inspection.trimming.dig_t1, 27504);
+
+        let worker = SensorWorker::spawn(
+            "fixed",
+            connected(FixedSensor(Reading::default())),
+            Duration::from_secs(1),
+        );
+        assert_eq!(worker.inspect().await.expect("inspect failed"), None);
+    }
+
+    struct UnreadableSensor;
+
+    impl Sensor for UnreadableSensor {
+        fn take_forced_measurement(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn read_sample(&mut self) -> anyhow::Result<Reading> {
+            anyhow::bail!("no acknowledge")
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_failing_stage() {
+        let worker = SensorWorker::spawn(
+            "unreadable",
+            connected(UnreadableSensor),
+            Duration::from_secs(1),
+        );
+
+        let err = worker.measure().await.expect_err("read should fail");
+        assert_eq!(err.stage(), Some(Stage::Read));
+        assert_eq!(err.to_string(), "read failed: no acknowledge");
+    }
+
+    #[tokio::test]
+    async fn times_out_on_hung_sensor() {
+        let (release, hang) = mpsc::channel();
+        let worker = SensorWorker::spawn(
+            "hung",
+            connected(HungSensor(hang)),
+            Duration::from_millis(20),
+        );
+
+        let err = worker
+            .measure()
+            .await
+            .expect_err("hung sensor should time out");
+        assert!(err.to_string().contains("did not respond"), "{err}");
+
+        // the second request queues behind the hung one, the third is refused
+        assert!(worker.measure().await.is_err());
+        let err = worker
+            .measure()
+            .await
+            .expect_err("busy sensor should be refused");
+        assert!(err.to_string().contains("still busy"), "{err}");
+
+        drop(release);
+    }
+
+    #[test]
+    fn retries_initialisation_with_backoff() {
+        let device = FakeBme280::new(0x76, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        device.unplug();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let mut supervisor = Supervisor::new("flaky", fake_bme280(&device, &attempts));
+
+        let err = supervisor
+            .measure()
+            .expect_err("unplugged sensor should fail");
+        assert_eq!(err.stage(), Some(Stage::Init));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // waiting out the backoff does not touch the bus
+        let err = supervisor
+            .measure()
+            .expect_err("unplugged sensor should fail");
+        assert!(matches!(err, Failure::Backoff { .. }), "{err}");
+        assert_eq!(err.stage(), None);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(supervisor.backoff, MIN_BACKOFF * 2);
+
+        device.plug_in();
+        supervisor.retry_at = Instant::now();
+        let reading = supervisor.measure().expect("replugged sensor should work");
+        assert_eq!(reading.temperature, Some(25.08));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(supervisor.backoff, MIN_BACKOFF);
+    }
+
+    #[test]
+    fn reinitialises_after_repeated_failures() {
+        let device = FakeBme280::new(0x76, Calibration::DATASHEET);
+        device.set_adc(519_888, 415_148, 30_000);
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let mut supervisor = Supervisor::new("replugged", fake_bme280(&device, &attempts));
+        supervisor.measure().expect("sensor should work");
+
+        device.unplug();
+        for _ in 0..REINIT_AFTER_FAILURES {
+            let err = supervisor
+                .measure()
+                .expect_err("unplugged sensor should fail");
+            assert_eq!(err.stage(), Some(Stage::Measure));
+        }
+        // the setup failed as well, so the bus is opened again next time
+        assert!(supervisor.sensor.is_none());
+
+        // the chip comes back from power-on reset without any configuration
+        device.plug_in();
+        assert_eq!(device.register(REGISTER_CTRL_HUM), 0);
+        supervisor.retry_at = Instant::now();
+
+        let reading = supervisor.measure().expect("replugged sensor should work");
+        assert!(reading.humidity.is_some());
+        assert_eq!(device.register(REGISTER_CTRL_HUM), 0b100);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+}